@@ -0,0 +1,159 @@
+use crate::codegen::gen_text;
+use crate::data::*;
+
+// the same tree as codegen, written as AsciiDoc. a level-1 header becomes the `=` document title.
+// entities left by the parser, such as `&lt;`, pass through AsciiDoc text unchanged but are turned back
+// into characters in code and math, which AsciiDoc shows verbatim. galleries, columns
+// and containers are flattened into their blocks and link cards become plain links
+pub fn gen_asciidoc(doc: &Document) -> String {
+    let mut gen = AsciiDoc { adoc: String::new(), footnotes: &doc.footnotes };
+    gen.gen_blocks(&doc.content);
+    gen.adoc
+}
+
+struct AsciiDoc<'a> {
+    adoc: String,
+    footnotes: &'a Vec<Vec<Span>>,
+}
+
+impl AsciiDoc<'_> {
+    fn gen_blocks(&mut self, content: &[Block]) {
+        for block in content {
+            self.gen_block(block);
+        }
+    }
+
+    fn gen_block(&mut self, block: &Block) {
+        match block {
+            Block::Header { prims, level, id } => {
+                let title = self.prims(prims);
+                self.adoc.push_str(&format!("[[{}]]\n{} {}\n\n", id, "=".repeat(*level as usize), title));
+            },
+            Block::Blockquote { lines, cite } => {
+                match cite {
+                    Some(cite) => {
+                        let cite = self.spans(cite);
+                        self.adoc.push_str(&format!("[quote, {}]\n", cite));
+                    },
+                    None => self.adoc.push_str("[quote]\n"),
+                }
+                self.adoc.push_str("____\n");
+                let lines: Vec<String> = lines.iter().map(|line| self.spans(line)).collect();
+                self.adoc.push_str(&lines.join("\n\n"));
+                self.adoc.push_str("\n____\n\n");
+            },
+            Block::ListElement(list) => {
+                self.gen_list(list, 1);
+                self.adoc.push('\n');
+            },
+            Block::Image { title, url, .. } => {
+                let title = gen_text(title).replace(']', "\\]");
+                self.adoc.push_str(&format!("image::{}[{}]\n\n", url, title));
+            },
+            Block::Gallery { images: content } | Block::Container { content, .. } => self.gen_blocks(content),
+            Block::Columns { columns } => {
+                for column in columns {
+                    self.gen_blocks(column);
+                }
+            },
+            Block::LinkCard { title, url, .. } => {
+                let title = if title.is_empty() { url } else { title };
+                self.adoc.push_str(&format!("{}[{}]\n\n", url, title.replace(']', "\\]")));
+            },
+            Block::MathBlock { math } => self.adoc.push_str(&format!("[stem]\n++++\n{}\n++++\n\n", unescape(math.trim()))),
+            Block::CodeBlock { lang, code } => {
+                if lang.is_empty() {
+                    self.adoc.push_str("----\n");
+                } else {
+                    self.adoc.push_str(&format!("[source,{}]\n----\n", lang));
+                }
+                self.adoc.push_str(&unescape(code.strip_suffix('\n').unwrap_or(code)));
+                self.adoc.push_str("\n----\n\n");
+            },
+            Block::Table { caption, head, body } => {
+                if let Some(caption) = caption {
+                    let caption = self.spans(caption);
+                    self.adoc.push_str(&format!(".{}\n", caption));
+                }
+                if !head.is_empty() {
+                    self.adoc.push_str("[options=\"header\"]\n");
+                }
+                self.adoc.push_str("|===\n");
+                for row in head.iter().chain(body) {
+                    let cells: Vec<String> = row.iter().map(|cell| format!("|{}", self.spans(cell).replace('|', "\\|"))).collect();
+                    self.adoc.push_str(&cells.join(" "));
+                    self.adoc.push('\n');
+                }
+                self.adoc.push_str("|===\n\n");
+            },
+            Block::Paragraph { spans } => {
+                let text = self.spans(spans);
+                self.adoc.push_str(&text);
+                self.adoc.push_str("\n\n");
+            },
+        }
+    }
+
+    // nesting is the number of markers, `*` for bullets and `.` for numbered items
+    fn gen_list(&mut self, list: &List, depth: usize) {
+        let marker = if list.ordered { "." } else { "*" }.repeat(depth);
+        for item in &list.items {
+            let text = self.spans(&item.spans);
+            self.adoc.push_str(&format!("{} {}\n", marker, text));
+            self.gen_list(&item.list, depth + 1);
+        }
+    }
+
+    fn spans(&self, spans: &[Span]) -> String {
+        spans.iter().map(|span| match span {
+            Span::Bold { text } => format!("**{}**", self.spans(text)),
+            Span::Ital { text } => format!("__{}__", self.spans(text)),
+            Span::Annotation { text, .. } => self.spans(text),
+            Span::PrimElem(prim) => self.prim(prim),
+        }).collect()
+    }
+
+    fn prims(&self, prims: &[Prim]) -> String {
+        prims.iter().map(|prim| self.prim(prim)).collect()
+    }
+
+    fn prim(&self, prim: &Prim) -> String {
+        match prim {
+            Prim::Link { text, url } => format!("link:{}[{}]", url, self.prims(text).replace(']', "\\]")),
+            Prim::WikiLink { target, label } => format!("xref:{}.adoc[{}]", target, label.replace(']', "\\]")),
+            Prim::Math { math } => format!("stem:[{}]", unescape(math).replace(']', "\\]")),
+            Prim::Code { code } => format!("`+{}+`", unescape(code)),
+            Prim::Kbd { keys } => format!("kbd:[{}]", keys.join("+")),
+            Prim::FootnoteRef { id } => {
+                let note = self.footnotes.get(id - 1).map(|note| self.spans(note)).unwrap_or_default();
+                format!("footnote:[{}]", note.replace(']', "\\]"))
+            },
+            Prim::Time { date } => date.clone(),
+            Prim::Text { text } => text.clone(),
+        }
+    }
+}
+
+// the parser escapes only `<` and `>`
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<").replace("&gt;", ">")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::RenderOptions;
+    use crate::parser::parse_markdown;
+
+    fn asciidoc(doc: &str) -> String {
+        gen_asciidoc(&parse_markdown(doc, &RenderOptions::default().with_offline(true)))
+    }
+
+    #[test]
+    fn code_and_math_are_unescaped() {
+        let adoc = asciidoc("`a<b>` and $x < y$ but <b>\n\n```rust\nVec<u8>\n```\n\n$$\nx > 0\n$$\n");
+        assert!(adoc.contains("`+a<b>+` and stem:[x < y] but &lt;b&gt;"));
+        assert!(adoc.contains("----\nVec<u8>\n----"));
+        assert!(adoc.contains("++++\nx > 0\n++++"));
+    }
+}
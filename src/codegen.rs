@@ -1,66 +1,520 @@
-use std::io::{self, Write};
-use std::fs::File;
-use chrono::{Local, Datelike, Timelike};
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, TimeZone, Utc, Datelike, Timelike};
+use reqwest::Url;
+#[cfg(feature = "mathml")]
+use latex2mathml::{latex_to_mathml, DisplayStyle};
 
 use crate::data::*;
+use crate::options::{DateZone, Newline, RenderOptions};
+use crate::slug::slugify;
 
 use Block::*;
 use Span::*;
 use Prim::*;
 use Elem::*;
 
-pub fn gen_html(dest: &mut File, title: &String, toc: &List, content: &Vec<Block>, template: &Vec<Elem>) -> Result<(), io::Error> {
-    let mut codegen = CodeGen::new(dest);
-    codegen.gen_html(title, toc, content, template)
+pub fn gen_html<W: Write>(dest: &mut W, doc: &Document, template: &[Elem], options: &RenderOptions) -> Result<(), io::Error> {
+    let newline = options.newline.as_ref().map(|newline| match newline {
+        Newline::Lf => "\n",
+        Newline::CrLf => "\r\n",
+    });
+    // the many small writes are buffered, since the destination is usually a file
+    let mut codegen = CodeGen::new(Newlines { dest: BufWriter::new(dest), newline, verbatim: false, cr: false }, options);
+    codegen.gen_html(doc, template)?;
+    codegen.dest.flush()
 }
 
-struct CodeGen<'a> {
-    dest: &'a mut File,
+pub fn gen_feed_item(title: &str, content: &[Block], options: &RenderOptions) -> String {
+    let mut codegen = CodeGen::new(Vec::new(), options);
+    codegen.gen_content(content, 0).unwrap();
+    let html = String::from_utf8(codegen.dest).unwrap();
+
+    let summary = content.iter().find_map(|block| match block {
+        Paragraph { spans } => Some(gen_text(spans)),
+        _ => None,
+    }).unwrap_or_default();
+
+    // the parser escapes `<` and `>` but leaves `&`, which is invalid in xml unless it starts an entity
+    let mut item = String::new();
+    item.push_str(&format!("<title>{}</title>\n", escape_bare_ampersands(title)));
+    item.push_str(&format!("<summary>{}</summary>\n", escape_bare_ampersands(&summary)));
+    item.push_str(&format!("<content type=\"html\">{}</content>\n", escape_html(&html)));
+    item
+}
+
+pub fn gen_block_html(block: &Block, indent: usize, options: &RenderOptions) -> String {
+    let mut codegen = CodeGen::new(Vec::new(), options);
+    codegen.gen_block(block, indent).unwrap();
+    String::from_utf8(codegen.dest).unwrap()
+}
+
+// render blocks of one document separately, keeping numbering and the lead paragraph consistent
+pub fn gen_blocks_html(content: &[Block], indent: usize, options: &RenderOptions) -> Vec<String> {
+    let mut counts = Counts::default();
+    content.iter().map(|block| {
+        let mut codegen = CodeGen::new(Vec::new(), options);
+        codegen.counts = counts;
+        codegen.gen_block(block, indent).unwrap();
+        counts = codegen.counts;
+        String::from_utf8(codegen.dest).unwrap()
+    }).collect()
+}
+
+pub fn gen_toc_html(toc: &List, options: &RenderOptions) -> String {
+    let mut codegen = CodeGen::new(Vec::new(), options);
+    codegen.gen_toc_nav(toc, 0).unwrap();
+    String::from_utf8(codegen.dest).unwrap()
+}
+
+// plain text of spans, with the html escaping done by the parser left intact
+pub fn gen_text(spans: &Vec<Span>) -> String {
+    let mut text = String::new();
+    for span in spans {
+        match span {
+            Bold { text: spans } | Ital { text: spans } | Annotation { text: spans, .. } => text.push_str(&gen_text(spans)),
+            PrimElem(prim) => text.push_str(&gen_prim_text(prim)),
+        }
+    }
+    text
+}
+
+pub fn gen_prim_text(prim: &Prim) -> String {
+    match prim {
+        Link { text, .. } => text.iter().map(gen_prim_text).collect(),
+        Math { math } => math.clone(),
+        Code { code } => code.clone(),
+        Text { text } => text.clone(),
+        WikiLink { label, .. } => label.clone(),
+        Kbd { keys } => keys.join("+"),
+        FootnoteRef { .. } => String::new(),
+        Time { date } => date.clone(),
+    }
+}
+
+// running counts of blocks that are numbered or treated specially by position
+#[derive(Clone, Copy, Default)]
+struct Counts {
+    figures: usize,
+    tables: usize,
+    equations: usize,
+    paragraphs: usize,
+}
+
+// rewrites every newline in the output, except while verbatim is set
+struct Newlines<W: Write> {
+    dest: W,
+    newline: Option<&'static str>,
+    verbatim: bool,
+    cr: bool,
+}
+
+impl<W: Write> Write for Newlines<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let Some(newline) = self.newline.filter(|_| !self.verbatim) else {
+            self.dest.write_all(buf)?;
+            return Ok(buf.len());
+        };
+        let mut normalized = Vec::with_capacity(buf.len());
+        for &b in buf {
+            // a `\r` that does not start a `\r\n` is kept
+            if self.cr && b != b'\n' {
+                normalized.push(b'\r');
+            }
+            self.cr = b == b'\r';
+            match b {
+                b'\r' => {},
+                b'\n' => normalized.extend_from_slice(newline.as_bytes()),
+                b => normalized.push(b),
+            }
+        }
+        self.dest.write_all(&normalized)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.dest.flush()
+    }
+}
+
+// code blocks switch newline normalization off when their contents are kept verbatim
+trait Verbatim {
+    fn set_verbatim(&mut self, _verbatim: bool) {}
+}
+
+impl Verbatim for Vec<u8> {}
+
+impl<W: Write> Verbatim for Newlines<W> {
+    fn set_verbatim(&mut self, verbatim: bool) {
+        self.verbatim = verbatim;
+    }
+}
+
+struct CodeGen<'a, W: Write> {
+    dest: W,
+    options: &'a RenderOptions,
+    counts: Counts,
+    nested: usize,
 }
 
-impl<'a> CodeGen<'a> {
-    fn new(dest: &'a mut File) -> Self {
-        CodeGen { dest }
+impl<'a, W: Write + Verbatim> CodeGen<'a, W> {
+    fn new(dest: W, options: &'a RenderOptions) -> Self {
+        CodeGen { dest, options, counts: Counts::default(), nested: 0 }
+    }
+
+    fn gen_html(&mut self, doc: &Document, template: &[Elem]) -> Result<(), io::Error> {
+        let datetime = self.datetime(doc);
+        self.gen_template(doc, template, &datetime)
+    }
+
+    // an explicit datetime wins over the front matter `date`, which wins over the current time
+    fn datetime(&self, doc: &Document) -> DateTime<FixedOffset> {
+        if let Some(datetime) = self.options.datetime {
+            return self.in_timezone(datetime);
+        }
+        if let Some(date) = doc.front_matter.get("date") {
+            if let Ok(datetime) = DateTime::parse_from_rfc3339(date) {
+                return self.in_timezone(datetime);
+            }
+            if let Ok(date) = NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+                // a bare date is midnight in the chosen timezone
+                let midnight = date.and_hms_opt(0, 0, 0).unwrap();
+                let datetime = match &self.options.timezone {
+                    None | Some(DateZone::Local) => Local.from_local_datetime(&midnight).single().map(|datetime| datetime.fixed_offset()),
+                    Some(DateZone::Utc) => Some(Utc.from_utc_datetime(&midnight).fixed_offset()),
+                    Some(DateZone::Fixed(offset)) => offset.from_local_datetime(&midnight).single(),
+                };
+                if let Some(datetime) = datetime {
+                    return datetime;
+                }
+            }
+        }
+        match &self.options.timezone {
+            None => Local::now().fixed_offset(),
+            Some(_) => self.in_timezone(Utc::now().fixed_offset()),
+        }
+    }
+
+    fn in_timezone(&self, datetime: DateTime<FixedOffset>) -> DateTime<FixedOffset> {
+        match &self.options.timezone {
+            None => datetime,
+            Some(DateZone::Local) => datetime.with_timezone(&Local).fixed_offset(),
+            Some(DateZone::Utc) => datetime.with_timezone(&Utc).fixed_offset(),
+            Some(DateZone::Fixed(offset)) => datetime.with_timezone(offset),
+        }
     }
 
-    fn gen_html(&mut self, title: &String, toc: &List, content: &Vec<Block>, template: &Vec<Elem>) -> Result<(), io::Error> {
-        let datetime = Local::now();
+    fn gen_template(&mut self, doc: &Document, template: &[Elem], datetime: &DateTime<FixedOffset>) -> Result<(), io::Error> {
         for chunk in template {
             match chunk {
-                Title => write!(self.dest, "{}", title)?,
+                Title => write!(self.dest, "{}", doc.title)?,
                 Year   => write!(self.dest, "{:04}", datetime.year())?,
                 Month  => write!(self.dest, "{:02}", datetime.month())?,
                 Day    => write!(self.dest, "{:02}", datetime.day())?,
                 Hour   => write!(self.dest, "{:02}", datetime.hour())?,
                 Minute => write!(self.dest, "{:02}", datetime.minute())?,
                 Second => write!(self.dest, "{:02}", datetime.second())?,
-                Toc(indent) => self.gen_toc(toc, *indent)?,
-                Content(indent) => self.gen_content(content, *indent)?,
+                IsoDate => write!(self.dest, "{}", datetime.format("%Y-%m-%dT%H:%M:%S%:z"))?,
+                JsonLd => self.gen_json_ld(doc)?,
+                Tags => self.gen_tags(doc)?,
+                Toc(indent) => self.gen_toc(&doc.toc, *indent)?,
+                Content(indent) => self.gen_wrapped_content(doc, *indent)?,
                 Str(text) => write!(self.dest, "{}", text)?,
+                Asset(path) => {
+                    let asset = fs::read_to_string(path).map_err(|err| io::Error::new(err.kind(), format!("cannot read asset {}: {}", path, err)))?;
+                    write!(self.dest, "{}", asset)?;
+                },
+                Var(key) => {
+                    if let Some(value) = doc.front_matter.get(key) {
+                        write!(self.dest, "{}", escape_html(value))?;
+                    }
+                },
+                IfPresent { field, body, else_body } => {
+                    let present = match field.as_str() {
+                        "title" => !doc.title.is_empty(),
+                        "toc" => !doc.toc.items.is_empty(),
+                        "content" => !doc.content.is_empty(),
+                        _ => doc.front_matter.contains_key(field),
+                    };
+                    self.gen_template(doc, if present { body } else { else_body }, datetime)?;
+                },
             }
         }
         Ok(())
     }
 
+    // an Article object from the title, front matter and first image, omitting missing fields
+    fn gen_json_ld(&mut self, doc: &Document) -> Result<(), io::Error> {
+        let front = |key: &str| doc.front_matter.get(key).map(String::as_str);
+        let title = doc.title.replace("&lt;", "<").replace("&gt;", ">");
+        let image = doc.content.iter().find_map(|block| match block {
+            Image { url, .. } => Some(url),
+            Gallery { images } => images.iter().find_map(|image| match image {
+                Image { url, .. } => Some(url),
+                _ => None,
+            }),
+            _ => None,
+        });
+        let image = front("image").or(image.map(String::as_str)).map(|url| self.resolve_url(url));
+
+        let mut fields = vec![
+            (String::from("@context"), json_string("https://schema.org")),
+            (String::from("@type"), json_string(front("type").unwrap_or("Article"))),
+        ];
+        if let Some(headline) = front("headline").or((!title.is_empty()).then_some(title.as_str())) {
+            fields.push((String::from("headline"), json_string(headline)));
+        }
+        if let Some(author) = front("author").or(front("author.name")) {
+            fields.push((String::from("author"), format!("{{\"@type\": \"Person\", \"name\": {}}}", json_string(author))));
+        }
+        for (key, field) in [("date", "datePublished"), ("updated", "dateModified"), ("description", "description")] {
+            if let Some(value) = front(key) {
+                fields.push((String::from(field), json_string(value)));
+            }
+        }
+        if let Some(image) = image {
+            fields.push((String::from("image"), json_string(&image)));
+        }
+
+        let fields: Vec<String> = fields.iter().map(|(key, value)| format!("{}: {}", json_string(key), value)).collect();
+        write!(self.dest, "<script type=\"application/ld+json\">{{{}}}</script>", fields.join(", "))
+    }
+
+    // the `tags` front matter as a flow list, `[rust, parsing]`, or as plain comma separated words.
+    // `{tag}` in the tag url is replaced by the slug of each tag
+    fn gen_tags(&mut self, doc: &Document) -> Result<(), io::Error> {
+        let Some(value) = doc.front_matter.get("tags") else {
+            return Ok(());
+        };
+        let value = value.trim();
+        let list = value.strip_prefix('[').and_then(|value| value.strip_suffix(']')).unwrap_or(value);
+        let tags: Vec<&str> = list.split(',').map(|tag| tag.trim().trim_matches(['"', '\'']).trim()).filter(|tag| !tag.is_empty()).collect();
+        if tags.is_empty() {
+            return Ok(());
+        }
+
+        write!(self.dest, "<ul class=\"{}\">", self.options.tags_class)?;
+        for tag in tags {
+            match &self.options.tag_url {
+                Some(url) => write!(self.dest, "<li><a href=\"{}\">{}</a></li>", self.resolve_url(&url.replace("{tag}", &slugify(tag))), escape_html(tag))?,
+                None => write!(self.dest, "<li>{}</li>", escape_html(tag))?,
+            }
+        }
+        write!(self.dest, "</ul>")
+    }
+
     fn gen_toc(&mut self, toc: &List, indent: usize) -> Result<(), io::Error> {
         writeln!(self.dest)?;
-        self.gen_list(&toc, indent)
+        self.gen_toc_nav(toc, indent)
+    }
+
+    // with an aria label the toc becomes a labelled navigation landmark, which screen readers list
+    // and let the reader jump to; content lists are never wrapped
+    fn gen_toc_nav(&mut self, toc: &List, indent: usize) -> Result<(), io::Error> {
+        let Some(label) = self.options.toc_aria_label.as_ref().filter(|_| !toc.items.is_empty()) else {
+            return self.gen_toc_list(toc, indent, 1);
+        };
+        writeln!(self.dest, "{:>indent$}<nav aria-label=\"{}\">", " ", escape_html(label))?;
+        self.gen_toc_list(toc, indent + 2, 1)?;
+        writeln!(self.dest, "{:>indent$}</nav>", " ")
+    }
+
+    // like gen_list, but with the toc classes and without explicit numbers or loose items.
+    // the lists below the collapse depth are folded into a <details>
+    fn gen_toc_list(&mut self, toc: &List, indent: usize, depth: usize) -> Result<(), io::Error> {
+        if toc.items.is_empty() {
+            return Ok(());
+        }
+
+        let tag = if toc.ordered { "ol" } else { "ul" };
+        writeln!(self.dest, "{:>indent$}<{}{}>", " ", tag, class_attr(&self.options.toc_list_class))?;
+        for item in &toc.items {
+            writeln!(self.dest, "{:>indent$}  <li{}>", " ", class_attr(&self.options.toc_item_class))?;
+            write!(self.dest, "{:>indent$}    ", " ")?;
+            match item.spans.as_slice() {
+                [PrimElem(Link { text, url })] => {
+                    write!(self.dest, "<a href=\"{}\"{}>", self.resolve_url(url), class_attr(&self.options.toc_link_class))?;
+                    self.gen_prims(text)?;
+                    writeln!(self.dest, "</a>")?;
+                },
+                _ => {
+                    self.gen_spans(&item.spans)?;
+                    writeln!(self.dest)?;
+                },
+            }
+            match &self.options.toc_collapse {
+                Some((collapse, summary)) if depth == *collapse && !item.list.items.is_empty() => {
+                    writeln!(self.dest, "{:>indent$}    <details>", " ")?;
+                    writeln!(self.dest, "{:>indent$}      <summary>{}</summary>", " ", summary)?;
+                    self.gen_toc_list(&item.list, indent + 6, depth + 1)?;
+                    writeln!(self.dest, "{:>indent$}    </details>", " ")?;
+                },
+                _ => self.gen_toc_list(&item.list, indent + 4, depth + 1)?,
+            }
+            writeln!(self.dest, "{:>indent$}  </li>", " ")?;
+        }
+        writeln!(self.dest, "{:>indent$}</{}>", " ", tag)
+    }
+
+    fn gen_wrapped_content(&mut self, doc: &Document, indent: usize) -> Result<(), io::Error> {
+        let wrapper = self.options.content_wrapper.as_ref().filter(|_| !doc.content.is_empty() || self.options.wrap_empty_content);
+        let Some(tag) = wrapper else {
+            self.gen_content(&doc.content, indent)?;
+            return self.gen_footnotes(&doc.footnotes, indent);
+        };
+        writeln!(self.dest)?;
+        write!(self.dest, "{:>indent$}<{}{}>", " ", tag, class_attr(&self.options.content_wrapper_class))?;
+        self.gen_content(&doc.content, indent + 2)?;
+        self.gen_footnotes(&doc.footnotes, indent + 2)?;
+        write!(self.dest, "{:>indent$}</{}>", " ", tag)
     }
 
-    fn gen_content(&mut self, content: &Vec<Block>, indent: usize) -> Result<(), io::Error> {
+    fn gen_content(&mut self, content: &[Block], indent: usize) -> Result<(), io::Error> {
         writeln!(self.dest)?;
-        for block in content {
-            match block {
-                Header { prims, level, id } => self.gen_header(prims, level, id, indent)?,
-                Blockquote { lines } => self.gen_blockquote(lines, indent)?,
-                ListElement(list) => self.gen_list(list, indent)?,
-                Table { head, body } => self.gen_table(head, body, indent)?,
-                Image { title, url } => self.gen_image(title, url, indent)?,
-                LinkCard { title, image, url, description, site_name } => self.gen_link_card(title, image, url, description, site_name, indent)?,
-                MathBlock { math } => self.gen_math_block(math, indent)?,
-                CodeBlock { lang, code } => self.gen_code_block(lang, code, indent)?,
-                Paragraph { spans } => self.gen_paragraph(spans, indent)?,
+        if self.options.section_wrapping {
+            return self.gen_sections(content, indent);
+        }
+        let mut sections = 0;
+        for (i, block) in content.iter().enumerate() {
+            self.gen_back_to_top(block, &mut sections, indent)?;
+            self.gen_content_block(block, indent)?;
+            self.gen_mini_toc(&content[i..], indent)?;
+
+            // a streaming destination can send each block as soon as it is written
+            if self.options.flush_blocks {
+                self.dest.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    // after a level-2 header, the level-3 headers up to the next level-2 or level-1 header
+    fn gen_mini_toc(&mut self, section: &[Block], indent: usize) -> Result<(), io::Error> {
+        if !self.options.mini_toc || !matches!(section[0], Header { level: 2, .. }) {
+            return Ok(());
+        }
+        let subheaders: Vec<_> = section[1..].iter()
+            .take_while(|block| !matches!(block, Header { level: 1 | 2, .. }))
+            .filter_map(|block| match block {
+                Header { prims, level: 3, id } => Some((prims, id)),
+                _ => None,
+            })
+            .collect();
+        if subheaders.is_empty() {
+            return Ok(());
+        }
+
+        writeln!(self.dest, "{:>indent$}<nav class=\"mini-toc\">", " ")?;
+        writeln!(self.dest, "{:>indent$}  <ul>", " ")?;
+        for (prims, id) in subheaders {
+            // links inside the header would nest, so only their text is kept
+            let text: Vec<Prim> = prims.iter().flat_map(|prim| match prim {
+                Link { text, .. } => text.clone(),
+                prim => vec![ prim.clone() ],
+            }).collect();
+            write!(self.dest, "{:>indent$}    <li><a href=\"{}\">", " ", self.resolve_url(&format!("#{}", id)))?;
+            self.gen_prims(&text)?;
+            writeln!(self.dest, "</a></li>")?;
+        }
+        writeln!(self.dest, "{:>indent$}  </ul>", " ")?;
+        writeln!(self.dest, "{:>indent$}</nav>", " ")
+    }
+
+    // a link back to the top before every level-2 header but the first
+    fn gen_back_to_top(&mut self, block: &Block, sections: &mut usize, indent: usize) -> Result<(), io::Error> {
+        let (Some(text), Header { level: 2, .. }) = (&self.options.back_to_top, block) else {
+            return Ok(());
+        };
+        *sections += 1;
+        if *sections == 1 {
+            return Ok(());
+        }
+        writeln!(self.dest, "{:>indent$}<a class=\"back-to-top\" href=\"{}\">{}</a>", " ", self.options.back_to_top_target, text)
+    }
+
+    // wrap each header and the blocks up to the next header of the same or higher level in a section
+    fn gen_sections(&mut self, content: &[Block], indent: usize) -> Result<(), io::Error> {
+        let mut levels: Vec<u32> = Vec::new();
+        let mut sections = 0;
+        for (i, block) in content.iter().enumerate() {
+            if let Header { level, .. } = block {
+                while levels.last().is_some_and(|last| last >= level) {
+                    levels.pop();
+                    writeln!(self.dest, "{:>indent$}</section>", " ", indent = indent + 2 * levels.len())?;
+                }
+                self.gen_back_to_top(block, &mut sections, indent + 2 * levels.len())?;
+                writeln!(self.dest, "{:>indent$}<section>", " ", indent = indent + 2 * levels.len())?;
+                levels.push(*level);
+            }
+            self.gen_content_block(block, indent + 2 * levels.len())?;
+            self.gen_mini_toc(&content[i..], indent + 2 * levels.len())?;
+            if self.options.flush_blocks {
+                self.dest.flush()?;
+            }
+        }
+        while levels.pop().is_some() {
+            writeln!(self.dest, "{:>indent$}</section>", " ", indent = indent + 2 * levels.len())?;
+        }
+        Ok(())
+    }
+
+    fn gen_footnotes(&mut self, footnotes: &[Vec<Span>], indent: usize) -> Result<(), io::Error> {
+        if footnotes.is_empty() {
+            return Ok(());
+        }
+
+        writeln!(self.dest, "{:>indent$}<section class=\"footnotes\">", " ")?;
+        let symbolic = !self.options.footnote_symbols.is_empty();
+        writeln!(self.dest, "{:>indent$}  {}", " ", if symbolic { "<ul class=\"footnote-symbols\">" } else { "<ol>" })?;
+        for (i, note) in footnotes.iter().enumerate() {
+            write!(self.dest, "{:>indent$}    <li id=\"fn-{}\">", " ", i + 1)?;
+            if symbolic {
+                write!(self.dest, "<span class=\"footnote-marker\">{}</span> ", self.footnote_marker(i + 1))?;
             }
+            self.gen_spans(note)?;
+            writeln!(self.dest, " <a class=\"footnote-backref\" href=\"#fnref-{}\">&#8617;</a></li>", i + 1)?;
+        }
+        writeln!(self.dest, "{:>indent$}  {}", " ", if symbolic { "</ul>" } else { "</ol>" })?;
+        writeln!(self.dest, "{:>indent$}</section>", " ")
+    }
+
+    // pass each top-level block through the block hook if one is set
+    fn gen_content_block(&mut self, block: &Block, indent: usize) -> Result<(), io::Error> {
+        let Some(hook) = &self.options.block_hook else {
+            return self.gen_block(block, indent);
+        };
+        // keep the counts running across hooked blocks
+        let mut codegen = CodeGen::new(Vec::new(), self.options);
+        codegen.counts = self.counts;
+        codegen.gen_block(block, indent)?;
+        self.counts = codegen.counts;
+        let html = String::from_utf8(codegen.dest).unwrap();
+        write!(self.dest, "{}", hook(block, &html))
+    }
+
+    // cycle through the symbols, doubling them on each pass: *, †, ‡, **, ††, ...
+    fn footnote_marker(&self, id: usize) -> String {
+        let symbols = &self.options.footnote_symbols;
+        if symbols.is_empty() {
+            return id.to_string();
+        }
+        symbols[(id - 1) % symbols.len()].repeat((id - 1) / symbols.len() + 1)
+    }
+
+    fn gen_block(&mut self, block: &Block, indent: usize) -> Result<(), io::Error> {
+        match block {
+            Header { prims, level, id } => self.gen_header(prims, level, id, indent)?,
+            Blockquote { lines, cite } => self.gen_blockquote(lines, cite, indent)?,
+            ListElement(list) => self.gen_list(list, indent)?,
+            Table { caption, head, body } => self.gen_table(caption, head, body, indent)?,
+            Image { title, url, attrs } => self.gen_image(title, url, attrs, indent)?,
+            Gallery { images } => self.gen_gallery(images, indent)?,
+            Columns { columns } => self.gen_columns(columns, indent)?,
+            Container { tag, open, summary, content } => self.gen_container(tag, open, summary, content, indent)?,
+            LinkCard { title, image, url, description, site_name } => self.gen_link_card(title, image, url, description, site_name, indent)?,
+            MathBlock { math } => self.gen_math_block(math, indent)?,
+            CodeBlock { lang, code } => self.gen_code_block(lang, code, indent)?,
+            Paragraph { spans } => self.gen_paragraph(spans, indent)?,
         }
         Ok(())
     }
@@ -71,7 +525,19 @@ impl<'a> CodeGen<'a> {
         writeln!(self.dest, "</h{}>", *level)
     }
 
-    fn gen_blockquote(&mut self, lines: &Vec<Vec<Span>>, indent: usize) -> Result<(), io::Error> {
+    fn gen_blockquote(&mut self, lines: &Vec<Vec<Span>>, cite: &Option<Vec<Span>>, indent: usize) -> Result<(), io::Error> {
+        let Some(cite) = cite else {
+            return self.gen_quote_lines(lines, indent);
+        };
+        writeln!(self.dest, "{:>indent$}<figure class=\"quote\">", " ")?;
+        self.gen_quote_lines(lines, indent + 2)?;
+        write!(self.dest, "{:>indent$}  <figcaption>&mdash; <cite>", " ")?;
+        self.gen_spans(cite)?;
+        writeln!(self.dest, "</cite></figcaption>")?;
+        writeln!(self.dest, "{:>indent$}</figure>", " ")
+    }
+
+    fn gen_quote_lines(&mut self, lines: &Vec<Vec<Span>>, indent: usize) -> Result<(), io::Error> {
         writeln!(self.dest, "{:>indent$}<blockquote>", " ")?;
         for spans in lines {
             write!(self.dest, "{:>indent$}  <p>", " ")?;
@@ -86,13 +552,43 @@ impl<'a> CodeGen<'a> {
             return Ok(());
         }
 
-        writeln!(self.dest, "{:>indent$}<{}>", " ", if list.ordered { "ol" } else { "ul" })?;
-        for item in &list.items {
-            writeln!(self.dest, "{:>indent$}  <li>", " ")?;
+        // explicit numbers only show up where they break the sequence
+        let start = list.items[0].number.filter(|&n| list.ordered && n != 1);
+        match start {
+            Some(start) => writeln!(self.dest, "{:>indent$}<ol start=\"{}\">", " ", start)?,
+            None => writeln!(self.dest, "{:>indent$}<{}>", " ", if list.ordered { "ol" } else { "ul" })?,
+        }
+        let mut current = start.unwrap_or(1);
+        let mut previous = list.items[0].number;
+        for (i, item) in list.items.iter().enumerate() {
+            // the source bullet, for stylesheets that tell `-` and `+` items apart
+            let marker = match item.marker {
+                Some(marker) if self.options.list_markers => format!(" data-marker=\"{}\"", marker),
+                _ => String::new(),
+            };
+            match item.number {
+                Some(n) if list.ordered && i > 0 && n != current + 1 && Some(n) != previous => {
+                    writeln!(self.dest, "{:>indent$}  <li value=\"{}\">", " ", n)?;
+                    current = n;
+                },
+                _ => {
+                    writeln!(self.dest, "{:>indent$}  <li{}>", " ", marker)?;
+                    if i > 0 {
+                        current += 1;
+                    }
+                },
+            }
+            previous = item.number;
             
             write!(self.dest, "{:>indent$}    ", " ")?;
-            self.gen_spans(&item.spans)?;
-            writeln!(self.dest)?;
+            if list.loose {
+                write!(self.dest, "<p>")?;
+                self.gen_spans(&item.spans)?;
+                writeln!(self.dest, "</p>")?;
+            } else {
+                self.gen_spans(&item.spans)?;
+                writeln!(self.dest)?;
+            }
             self.gen_list(&item.list, indent + 4)?;
             
             writeln!(self.dest, "{:>indent$}  </li>", " ")?;
@@ -100,16 +596,86 @@ impl<'a> CodeGen<'a> {
         writeln!(self.dest, "{:>indent$}</{}>", " ", if list.ordered { "ol" } else { "ul" })
     }
 
-    fn gen_image(&mut self, title: &Vec<Prim>, url: &String, indent: usize) -> Result<(), io::Error> {
-        writeln!(self.dest, "{:>indent$}<div class=\"image\">", " ")?;
-        writeln!(self.dest, "{:>indent$}  <img src=\"{}\">", " ", *url)?;
+    fn gen_image(&mut self, title: &Vec<Span>, url: &str, attrs: &ImageAttrs, indent: usize) -> Result<(), io::Error> {
+        self.counts.figures += 1;
+        let class = match &attrs.align {
+            Some(align) => format!("image align-{}", align),
+            None => String::from("image"),
+        };
+        if self.options.block_ids {
+            writeln!(self.dest, "{:>indent$}<div class=\"{}\" id=\"fig-{}\">", " ", class, self.counts.figures)?;
+        } else {
+            writeln!(self.dest, "{:>indent$}<div class=\"{}\">", " ", class)?;
+        }
+
+        let mut style = String::new();
+        if let Some(width) = &attrs.width {
+            style.push_str(&format!("width: {};", width));
+        }
+        if let Some(height) = &attrs.height {
+            style.push_str(&format!("{}height: {};", if style.is_empty() { "" } else { " " }, height));
+        }
+        if style.is_empty() {
+            writeln!(self.dest, "{:>indent$}  <img src=\"{}\">", " ", self.resolve_url(url))?;
+        } else {
+            writeln!(self.dest, "{:>indent$}  <img src=\"{}\" style=\"{}\">", " ", self.resolve_url(url), style)?;
+        }
         write!(self.dest, "{:>indent$}  <p class=\"caption\">", " ")?;
-        self.gen_prims(title)?;
+        self.gen_spans(title)?;
         writeln!(self.dest, "</p>")?;
         writeln!(self.dest, "{:>indent$}</div>", " ")
     }
 
-    fn gen_link_card(&mut self, title: &String, image: &Option<String>, url: &String, description: &Option<String>, site_name: &Option<String>, indent: usize) -> Result<(), io::Error> {
+    fn gen_gallery(&mut self, images: &Vec<Block>, indent: usize) -> Result<(), io::Error> {
+        writeln!(self.dest, "{:>indent$}<div class=\"gallery\">", " ")?;
+        for image in images {
+            self.gen_block(image, indent + 2)?;
+        }
+        writeln!(self.dest, "{:>indent$}</div>", " ")
+    }
+
+    fn gen_columns(&mut self, columns: &Vec<Vec<Block>>, indent: usize) -> Result<(), io::Error> {
+        writeln!(self.dest, "{:>indent$}<div class=\"columns\">", " ")?;
+        self.nested += 1;
+        for column in columns {
+            writeln!(self.dest, "{:>indent$}  <div class=\"column\">", " ")?;
+            for block in column {
+                self.gen_block(block, indent + 4)?;
+            }
+            writeln!(self.dest, "{:>indent$}  </div>", " ")?;
+        }
+        self.nested -= 1;
+        writeln!(self.dest, "{:>indent$}</div>", " ")
+    }
+
+    fn gen_container(&mut self, tag: &str, open: &str, summary: &Option<Vec<Span>>, content: &Vec<Block>, indent: usize) -> Result<(), io::Error> {
+        writeln!(self.dest, "{:>indent$}{}", " ", open)?;
+        if let Some(summary) = summary {
+            write!(self.dest, "{:>indent$}  <summary>", " ")?;
+            self.gen_spans(summary)?;
+            writeln!(self.dest, "</summary>")?;
+        }
+        self.nested += 1;
+        for block in content {
+            self.gen_block(block, indent + 2)?;
+        }
+        self.nested -= 1;
+        writeln!(self.dest, "{:>indent$}</{}>", " ", tag)
+    }
+
+    fn gen_link_card(&mut self, title: &String, image: &Option<String>, url: &str, description: &Option<String>, site_name: &Option<String>, indent: usize) -> Result<(), io::Error> {
+        let url = &self.resolve_url(url);
+        if let Some(renderer) = &self.options.link_card_renderer {
+            let card = LinkCardData {
+                title,
+                image: image.as_deref(),
+                url,
+                description: description.as_deref(),
+                site_name: site_name.as_deref(),
+            };
+            return renderer(&card, &mut self.dest);
+        }
+
         writeln!(self.dest, "{:>indent$}<div class=\"linkcard\"><a class=\"linkcard-link\" href=\"{}\">", "", url)?;
         writeln!(self.dest, "{:>indent$}  <div class=\"linkcard-text\">", "")?;
         writeln!(self.dest, "{:>indent$}    <h3 class=\"linkcard-title\">{}</h3>", "", title)?;
@@ -119,19 +685,32 @@ impl<'a> CodeGen<'a> {
         writeln!(self.dest, "{:>indent$}    <img  class=\"linkcard-favicon\" src=\"http://www.google.com/s2/favicons?domain={}\"><span  class=\"linkcard-sitename\">{}</span>", "", url, site_name.clone().unwrap_or(url.clone()))?;
         writeln!(self.dest, "{:>indent$}  </div>", "")?;
         if let Some(img) = image {
-            writeln!(self.dest, "{:>indent$}  <img class=\"linkcard-image\" src=\"{}\">", "", img)?;
+            writeln!(self.dest, "{:>indent$}  <img class=\"linkcard-image\" src=\"{}\">", "", self.safe_url(img.clone()))?;
         }
         writeln!(self.dest, "{:>indent$}</a></div>", "")
     }
 
-    fn gen_table(&mut self, head: &Vec<Vec<String>>, body: &Vec<Vec<String>>, indent: usize) -> Result<(), io::Error> {
-        writeln!(self.dest, "{:>indent$}<table>", " ")?;
+    fn gen_table(&mut self, caption: &Option<Vec<Span>>, head: &Vec<Vec<Vec<Span>>>, body: &Vec<Vec<Vec<Span>>>, indent: usize) -> Result<(), io::Error> {
+        self.counts.tables += 1;
+        if self.options.block_ids {
+            writeln!(self.dest, "{:>indent$}<table id=\"table-{}\">", " ", self.counts.tables)?;
+        } else {
+            writeln!(self.dest, "{:>indent$}<table>", " ")?;
+        }
+
+        if let Some(caption) = caption {
+            write!(self.dest, "{:>indent$}  <caption>", " ")?;
+            self.gen_spans(caption)?;
+            writeln!(self.dest, "</caption>")?;
+        }
 
         writeln!(self.dest, "{:>indent$}  <thead>", " ")?;
         for row in head {
             writeln!(self.dest, "{:>indent$}    <tr>", " ")?;
             for data in row {
-                writeln!(self.dest, "{:>indent$}      <td>{}</td>", " ", *data)?;
+                write!(self.dest, "{:>indent$}      <td>", " ")?;
+                self.gen_spans(data)?;
+                writeln!(self.dest, "</td>")?;
             }
             writeln!(self.dest, "{:>indent$}    </tr>", " ")?;
         }
@@ -141,7 +720,9 @@ impl<'a> CodeGen<'a> {
         for row in body {
             writeln!(self.dest, "{:>indent$}    <tr>", " ")?;
             for data in row {
-                writeln!(self.dest, "{:>indent$}      <td>{}</td>", " ", *data)?;
+                write!(self.dest, "{:>indent$}      <td>", " ")?;
+                self.gen_spans(data)?;
+                writeln!(self.dest, "</td>")?;
             }
             writeln!(self.dest, "{:>indent$}    </tr>", " ")?;
         }
@@ -151,17 +732,79 @@ impl<'a> CodeGen<'a> {
     }
 
     fn gen_math_block(&mut self, math: &String, indent: usize) -> Result<(), io::Error> {
+        if !self.options.number_equations {
+            return self.gen_display_math(math, indent);
+        }
+        // the equation takes the remaining width and the number sits on the right, without a stylesheet
+        self.counts.equations += 1;
+        let style = "display:grid;grid-template-columns:1fr auto;align-items:center";
+        writeln!(self.dest, "{:>indent$}<div class=\"equation\" id=\"eq-{}\" style=\"{}\">", " ", self.counts.equations, style)?;
+        self.gen_display_math(math, indent + 2)?;
+        writeln!(self.dest, "{:>indent$}  <span class=\"equation-number\">({})</span>", " ", self.counts.equations)?;
+        writeln!(self.dest, "{:>indent$}</div>", " ")
+    }
+
+    fn gen_display_math(&mut self, math: &String, indent: usize) -> Result<(), io::Error> {
+        if let Some(mathml) = self.to_mathml(math, true) {
+            return writeln!(self.dest, "{:>indent$}{}", " ", mathml);
+        }
+        if self.options.math_noscript {
+            return writeln!(self.dest, "{:>indent$}<p>\\[{}\\]</p><noscript><pre>$${}$$</pre></noscript>", " ", math, math);
+        }
         writeln!(self.dest, "{:>indent$}<p>\\[{}\\]</p>", " ", math)
     }
 
+    // falls back to tex delimiters when the conversion fails
+    #[cfg(feature = "mathml")]
+    fn to_mathml(&self, math: &str, display: bool) -> Option<String> {
+        if !self.options.mathml {
+            return None;
+        }
+        let tex = math.replace("&lt;", "<").replace("&gt;", ">");
+        latex_to_mathml(&tex, if display { DisplayStyle::Block } else { DisplayStyle::Inline }).ok()
+    }
+
+    #[cfg(not(feature = "mathml"))]
+    fn to_mathml(&self, _math: &str, _display: bool) -> Option<String> {
+        None
+    }
+
     fn gen_code_block(&mut self, lang: &String, code: &String, indent: usize) -> Result<(), io::Error> {
-        write!(self.dest, "{:>indent$}<pre><code class=\"language-{}\">", " ", if lang == "" { "plaintext" } else { lang })?;
-        write!(self.dest, "{}", code)?;
-        writeln!(self.dest, "</code></pre>")
+        let lang = self.options.lang_aliases.get(lang).map_or(lang.as_str(), String::as_str);
+
+        // the language goes into attributes, so only allow a plain token
+        let valid = !lang.is_empty() && lang.chars().all(|c| c.is_ascii_alphanumeric() || "+-#".contains(c));
+        let lang = if valid { lang } else { "plaintext" };
+        let prefix = &self.options.code_class_prefix;
+        if self.options.copy_button {
+            writeln!(self.dest, "{:>indent$}<div class=\"code-wrapper\">", " ")?;
+            writeln!(self.dest, "{:>indent$}  <button class=\"copy\" data-lang=\"{}\">Copy</button>", " ", lang)?;
+            write!(self.dest, "{:>indent$}  <pre><code class=\"{}{}\">", " ", prefix, lang)?;
+        } else {
+            write!(self.dest, "{:>indent$}<pre><code class=\"{}{}\">", " ", prefix, lang)?;
+        }
+        self.dest.set_verbatim(self.options.preserve_code_newlines);
+        match self.options.tab_width {
+            Some(width) if width > 0 => write!(self.dest, "{}", expand_tabs(code, width))?,
+            _ => write!(self.dest, "{}", code)?,
+        }
+        self.dest.set_verbatim(false);
+        writeln!(self.dest, "</code></pre>")?;
+        if self.options.copy_button {
+            writeln!(self.dest, "{:>indent$}</div>", " ")?;
+        }
+        Ok(())
     }
 
     fn gen_paragraph(&mut self, spans: &Vec<Span>, indent: usize) -> Result<(), io::Error> {
-        write!(self.dest, "{:>indent$}<p>", " ")?;
+        // the lead is the first top-level paragraph, not one inside columns or a container
+        if self.nested == 0 {
+            self.counts.paragraphs += 1;
+        }
+        match &self.options.lead_class {
+            Some(class) if self.nested == 0 && self.counts.paragraphs == 1 => write!(self.dest, "{:>indent$}<p class=\"{}\">", " ", class)?,
+            _ => write!(self.dest, "{:>indent$}<p>", " ")?,
+        }
         self.gen_spans(spans)?;
         writeln!(self.dest, "</p>")
     }
@@ -171,6 +814,7 @@ impl<'a> CodeGen<'a> {
             match span {
                 Bold { text } => self.gen_bold(text)?,
                 Ital { text } => self.gen_ital(text)?,
+                Annotation { text, note } => self.gen_annotation(text, note)?,
                 PrimElem(prim) => self.gen_primary(prim)?,
             }
         }
@@ -189,6 +833,13 @@ impl<'a> CodeGen<'a> {
         write!(self.dest, "</em>")
     }
 
+    // the note goes into a title attribute, so only its text is kept
+    fn gen_annotation(&mut self, text: &Vec<Span>, note: &Vec<Span>) -> Result<(), io::Error> {
+        write!(self.dest, "<span class=\"annotation\" title=\"{}\">", gen_text(note).replace('"', "&quot;"))?;
+        self.gen_spans(text)?;
+        write!(self.dest, "</span>")
+    }
+
     fn gen_prims(&mut self, prims: &Vec<Prim>) -> Result<(), io::Error> {
         for prim in prims {
             self.gen_primary(prim)?;
@@ -198,14 +849,270 @@ impl<'a> CodeGen<'a> {
 
     fn gen_primary(&mut self, prim: &Prim) -> Result<(), io::Error> {
         match prim {
+            Link { text, url } if self.options.obfuscate_emails && url.starts_with("mailto:") => {
+                // spell out every character as an entity to keep the address from scrapers
+                write!(self.dest, "<a href=\"{}\">{}</a>", obfuscate(&self.resolve_url(url)), obfuscate(&text.iter().map(gen_prim_text).collect::<String>()))
+            },
             Link { text, url } => {
-                write!(self.dest, "<a href=\"{}\">", *url)?;
+                write!(self.dest, "<a href=\"{}\">", self.resolve_url(url))?;
                 self.gen_prims(text)?;
                 write!(self.dest, "</a>")
             },
-            Math { math } => write!(self.dest, "\\({}\\)", *math),
+            Math { math } => match self.to_mathml(math, false) {
+                Some(mathml) => write!(self.dest, "{}", mathml),
+                None if self.options.math_noscript => write!(self.dest, "\\({}\\)<noscript><code>${}$</code></noscript>", *math, *math),
+                None => write!(self.dest, "\\({}\\)", *math),
+            },
             Code { code } => write!(self.dest, "<code>{}</code>", *code),
             Text { text } => write!(self.dest, "{}", text),
+            WikiLink { target, label } => self.gen_wiki_link(target, label),
+            Kbd { keys } => self.gen_kbd(keys),
+            Time { date } => write!(self.dest, "<time datetime=\"{}\">{}</time>", date, date),
+            FootnoteRef { id } => write!(self.dest, "<sup class=\"footnote-ref\"><a id=\"fnref-{}\" href=\"#fn-{}\">{}</a></sup>", id, id, self.footnote_marker(*id)),
         }
     }
-}
\ No newline at end of file
+
+    fn gen_kbd(&mut self, keys: &[String]) -> Result<(), io::Error> {
+        for (i, key) in keys.iter().enumerate() {
+            if i > 0 {
+                write!(self.dest, "+")?;
+            }
+            write!(self.dest, "<kbd>{}</kbd>", key)?;
+        }
+        Ok(())
+    }
+
+    // targets the resolver does not know are marked as broken
+    fn gen_wiki_link(&mut self, target: &str, label: &str) -> Result<(), io::Error> {
+        let slug = format!("{}.html", slugify(target));
+        match &self.options.wiki_link_resolver {
+            None => write!(self.dest, "<a href=\"{}\">{}</a>", self.resolve_url(&slug), label),
+            Some(resolver) => match resolver(target) {
+                Some(url) => write!(self.dest, "<a href=\"{}\">{}</a>", self.resolve_url(&url), label),
+                None => write!(self.dest, "<a class=\"broken\" href=\"{}\">{}</a>", self.resolve_url(&slug), label),
+            },
+        }
+    }
+
+    // relative urls are joined onto `base_url` when one is given. anchors and absolute URLs,
+    // given or returned by the resolver, are kept as they are
+    fn resolve_url(&self, url: &str) -> String {
+        let absolute = |url: &str| url.starts_with('#') || url.starts_with("//") || url_scheme(url).is_some();
+        if absolute(url) {
+            return self.safe_url(url.to_string());
+        }
+        let url = match &self.options.url_resolver {
+            Some(resolver) => resolver(url),
+            None => url.to_string(),
+        };
+        let joined = match &self.options.base_url {
+            Some(base) if !absolute(&url) => Url::parse(base).and_then(|base| base.join(&url)).map(String::from).ok(),
+            _ => None,
+        };
+        self.safe_url(joined.unwrap_or(url))
+    }
+
+    // keep relative URLs and anchors, and absolute URLs only with an allowed scheme
+    fn safe_url(&self, url: String) -> String {
+        // browsers ignore whitespace and control characters in the scheme
+        let cleaned: String = url.chars().filter(|c| !c.is_ascii_whitespace() && !c.is_ascii_control()).collect();
+        if let Some(scheme) = url_scheme(&cleaned) {
+            let blocked = self.options.sanitize && ["javascript", "vbscript", "data"].iter().any(|name| scheme.eq_ignore_ascii_case(name));
+            if blocked || !self.options.allowed_schemes.iter().any(|allowed| scheme.eq_ignore_ascii_case(allowed)) {
+                return String::from("#");
+            }
+        }
+        url.replace('"', "&quot;")
+    }
+}
+
+// replace tabs with spaces up to the next tab stop; the code is escaped, so an entity is one column
+fn expand_tabs(code: &str, width: usize) -> String {
+    let mut expanded = String::new();
+    let mut column = 0;
+    let mut entity_end = 0;
+    for (i, c) in code.char_indices() {
+        match c {
+            _ if i < entity_end => {},
+            '\t' => {
+                let spaces = width - column % width;
+                expanded.push_str(&" ".repeat(spaces));
+                column += spaces;
+                continue;
+            },
+            '\n' => column = 0,
+            '&' => {
+                entity_end = i + entity_len(&code[i..]);
+                column += 1;
+            },
+            _ => column += 1,
+        }
+        expanded.push(c);
+    }
+    expanded
+}
+
+// the length of an `&name;` or `&#123;` entity at the start of text, or 0 for a bare `&`
+fn entity_len(text: &str) -> usize {
+    let body = &text[1..];
+    let hash = usize::from(body.starts_with('#'));
+    let name = body[hash..].find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(body.len() - hash);
+    if name > 0 && body[hash + name..].starts_with(';') {
+        hash + name + 2
+    } else {
+        0
+    }
+}
+
+// a quoted JSON string that is also safe inside a <script> element
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            '<' => json.push_str("\\u003c"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn obfuscate(text: &str) -> String {
+    text.chars().map(|c| format!("&#{};", c as u32)).collect()
+}
+
+fn class_attr(class: &Option<String>) -> String {
+    match class {
+        Some(class) => format!(" class=\"{}\"", class),
+        None => String::new(),
+    }
+}
+
+fn url_scheme(url: &str) -> Option<&str> {
+    let (scheme, _) = url.split_once(':')?;
+    let mut chars = scheme.chars();
+    let valid = chars.next()?.is_ascii_alphabetic() && chars.all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+    valid.then_some(scheme)
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// escape every `&` that does not already start an entity such as `&amp;` or `&#39;`
+pub fn escape_bare_ampersands(text: &str) -> String {
+    let mut escaped = String::new();
+    for (i, c) in text.char_indices() {
+        let entity = text[i + 1..].split_once(';').is_some_and(|(name, _)| {
+            !name.is_empty() && name.trim_start_matches('#').chars().all(char::is_alphanumeric)
+        });
+        match c {
+            '&' if !entity => escaped.push_str("&amp;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_markdown;
+
+    fn options() -> RenderOptions {
+        RenderOptions::default().with_offline(true)
+    }
+
+    #[test]
+    fn feed_item_escapes_bare_ampersands() {
+        let doc = parse_markdown("Fish & chips &amp; <peas>\n", &options());
+        let item = gen_feed_item("Salt & vinegar", &doc.content, &options());
+        assert!(item.contains("<title>Salt &amp; vinegar</title>"));
+        assert!(item.contains("<summary>Fish &amp; chips &amp; &lt;peas&gt;</summary>"));
+    }
+
+    #[test]
+    fn numbered_equations_lay_out_the_number_on_the_right() {
+        let options = options().with_number_equations(true);
+        let doc = parse_markdown("$$\nx = 1\n$$\n", &options);
+        let html = gen_blocks_html(&doc.content, 0, &options).concat();
+        assert!(html.contains("<div class=\"equation\" id=\"eq-1\" style=\"display:grid;grid-template-columns:1fr auto;align-items:center\">"));
+        let (math, number) = (html.find("\\[").unwrap(), html.find("equation-number").unwrap());
+        assert!(math < number);
+    }
+
+    // records the bytes written before each flush
+    #[derive(Default)]
+    struct Flushes {
+        pending: Vec<u8>,
+        chunks: Vec<String>,
+    }
+
+    impl Write for Flushes {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.pending.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            if !self.pending.is_empty() {
+                self.chunks.push(String::from_utf8(std::mem::take(&mut self.pending)).unwrap());
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn blocks_are_flushed_only_when_asked() {
+        let source = "para one\n\npara two\n\npara three\n";
+        for section_wrapping in [false, true] {
+            let options = options().with_section_wrapping(section_wrapping);
+            let doc = parse_markdown(source, &options);
+            let mut dest = Flushes::default();
+            gen_html(&mut dest, &doc, &[Content(0)], &options).unwrap();
+            assert_eq!(dest.chunks.len(), 1);
+
+            let options = options.with_flush_blocks(true);
+            let mut dest = Flushes::default();
+            gen_html(&mut dest, &doc, &[Content(0)], &options).unwrap();
+            assert_eq!(dest.chunks.len(), 3);
+            assert!(dest.chunks[1].contains("<p>para two</p>") && !dest.chunks[1].contains("three"));
+        }
+    }
+
+    #[test]
+    fn tabs_count_entities_as_one_column_and_bare_ampersands_as_text() {
+        assert_eq!(expand_tabs("x & y\tz\n&&\tw", 4), "x & y   z\n&&  w");
+        assert_eq!(expand_tabs("&lt;a&gt;\tb\n&#39;\tc", 4), "&lt;a&gt; b\n&#39;   c");
+        assert_eq!(expand_tabs("a &b\n\tc;", 4), "a &b\n    c;");
+    }
+
+    #[test]
+    fn anchors_and_absolute_urls_skip_the_base_url() {
+        let options = options().with_base_url("https://ex.com/blog/").with_mini_toc(true);
+        let doc = parse_markdown("## Intro\n\n### Detail\n\n[a](#Intro) [b](page.html) [c](https://x.y/z) [d](//cdn.example/x)\n", &options);
+        let mut dest = Vec::new();
+        gen_html(&mut dest, &doc, &[Toc(0), Content(0)], &options).unwrap();
+        let html = String::from_utf8(dest).unwrap();
+        assert!(html.contains("<a href=\"#Intro\">a</a>") && html.contains("<a href=\"https://ex.com/blog/page.html\">b</a>"));
+        assert!(html.contains("<a href=\"https://x.y/z\">c</a>") && html.contains("<a href=\"//cdn.example/x\">d</a>"));
+        assert!(html.contains("<li><a href=\"#Detail\">Detail</a></li>") && !html.contains("ex.com/blog/#"));
+    }
+}
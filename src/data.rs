@@ -1,41 +1,184 @@
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Document {
+    pub title: String,
+    pub toc: List,
+    pub content: Vec<Block>,
+    // the source byte range of each content block
+    pub ranges: Vec<Range<usize>>,
+    pub footnotes: Vec<Vec<Span>>,
+    pub front_matter: BTreeMap<String, String>,
+    pub warnings: Vec<Warning>,
+}
+
+// a non-fatal diagnostic at a byte offset into the source
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Warning {
+    pub offset: usize,
+    pub message: String,
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
 pub enum Block {
     Header { prims: Vec<Prim>, level: u32, id: String },
-    Blockquote { lines: Vec<Vec<Span>> },
+    Blockquote { lines: Vec<Vec<Span>>, cite: Option<Vec<Span>> },
     ListElement(List),
-    Image { title: Vec<Prim>, url: String },
+    Image { title: Vec<Span>, url: String, attrs: ImageAttrs },
+    Gallery { images: Vec<Block> },
+    Columns { columns: Vec<Vec<Block>> },
+    Container { tag: String, open: String, summary: Option<Vec<Span>>, content: Vec<Block> },
     LinkCard { title: String, image: Option<String>, url: String, description: Option<String>, site_name: Option<String> },
     MathBlock { math: String },
     CodeBlock { lang: String, code: String },
-    Table { head: Vec<Vec<String>>, body: Vec<Vec<String>> },
+    Table { caption: Option<Vec<Span>>, head: Vec<Vec<Vec<Span>>>, body: Vec<Vec<Vec<Span>>> },
     Paragraph { spans: Vec<Span> },
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
 pub enum Span {
     Bold { text: Vec<Span> },
     Ital { text: Vec<Span> },
+    Annotation { text: Vec<Span>, note: Vec<Span> },
     PrimElem(Prim),
 }
 
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
 pub enum Prim {
     Link { text: Vec<Prim>, url: String },
+    WikiLink { target: String, label: String },
     Math { math: String },
     Code { code: String },
+    Kbd { keys: Vec<String> },
+    FootnoteRef { id: usize },
+    Time { date: String },
     Text { text: String },
 }
 
+// variant tags without payloads, so downstream code can categorize nodes without matching every variant
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub enum BlockKind {
+    Header,
+    Blockquote,
+    List,
+    Image,
+    Gallery,
+    Columns,
+    Container,
+    LinkCard,
+    MathBlock,
+    CodeBlock,
+    Table,
+    Paragraph,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub enum SpanKind {
+    Bold,
+    Ital,
+    Annotation,
+    Link,
+    WikiLink,
+    Math,
+    Code,
+    Kbd,
+    FootnoteRef,
+    Time,
+    Text,
+}
+
+impl Block {
+    pub fn kind(&self) -> BlockKind {
+        match self {
+            Block::Header { .. } => BlockKind::Header,
+            Block::Blockquote { .. } => BlockKind::Blockquote,
+            Block::ListElement(_) => BlockKind::List,
+            Block::Image { .. } => BlockKind::Image,
+            Block::Gallery { .. } => BlockKind::Gallery,
+            Block::Columns { .. } => BlockKind::Columns,
+            Block::Container { .. } => BlockKind::Container,
+            Block::LinkCard { .. } => BlockKind::LinkCard,
+            Block::MathBlock { .. } => BlockKind::MathBlock,
+            Block::CodeBlock { .. } => BlockKind::CodeBlock,
+            Block::Table { .. } => BlockKind::Table,
+            Block::Paragraph { .. } => BlockKind::Paragraph,
+        }
+    }
+}
+
+impl Span {
+    pub fn kind(&self) -> SpanKind {
+        match self {
+            Span::Bold { .. } => SpanKind::Bold,
+            Span::Ital { .. } => SpanKind::Ital,
+            Span::Annotation { .. } => SpanKind::Annotation,
+            Span::PrimElem(prim) => prim.kind(),
+        }
+    }
+}
+
+impl Prim {
+    pub fn kind(&self) -> SpanKind {
+        match self {
+            Prim::Link { .. } => SpanKind::Link,
+            Prim::WikiLink { .. } => SpanKind::WikiLink,
+            Prim::Math { .. } => SpanKind::Math,
+            Prim::Code { .. } => SpanKind::Code,
+            Prim::Kbd { .. } => SpanKind::Kbd,
+            Prim::FootnoteRef { .. } => SpanKind::FootnoteRef,
+            Prim::Time { .. } => SpanKind::Time,
+            Prim::Text { .. } => SpanKind::Text,
+        }
+    }
+}
+
+// layout attributes from a trailing `{width=400 align=center}` on an image embed
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ImageAttrs {
+    pub width: Option<String>,
+    pub height: Option<String>,
+    pub align: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct LinkCardData<'a> {
+    pub title: &'a str,
+    pub image: Option<&'a str>,
+    pub url: &'a str,
+    pub description: Option<&'a str>,
+    pub site_name: Option<&'a str>,
+}
+
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct List {
     pub ordered: bool,
+    pub loose: bool,
     pub items: Vec<ListItem>,
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 pub struct ListItem {
     pub spans: Vec<Span>,
     pub list: List,
+    pub number: Option<u32>,
+    // the source bullet, `-` or `+`; numbered items and toc entries have none
+    pub marker: Option<char>,
 }
 
 #[derive(Debug)]
@@ -47,7 +190,13 @@ pub enum Elem {
     Hour,
     Minute,
     Second,
+    IsoDate,
+    JsonLd,
+    Tags,
     Toc(usize),
     Content(usize),
     Str(String),
+    Var(String),
+    Asset(String),
+    IfPresent { field: String, body: Vec<Elem>, else_body: Vec<Elem> },
 }
\ No newline at end of file
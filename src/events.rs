@@ -0,0 +1,449 @@
+use crate::codegen::gen_blocks_html;
+use crate::data::{Block, ImageAttrs, List, ListItem, Prim, Span};
+use crate::options::RenderOptions;
+
+// the tree flattened into start and end tags around leaf events, in the style of pulldown-cmark
+#[derive(Clone, Debug, PartialEq)]
+pub enum Tag {
+    Heading { level: u32, id: String },
+    BlockQuote,
+    Cite,
+    List { ordered: bool, start: Option<u32> },
+    Item { number: Option<u32>, marker: Option<char> },
+    Image { url: String, width: Option<String>, height: Option<String>, align: Option<String> },
+    Gallery,
+    Columns,
+    Column,
+    Container { tag: String, open: String },
+    Summary,
+    CodeBlock { lang: String },
+    Table,
+    TableCaption,
+    TableHead,
+    TableBody,
+    TableRow,
+    TableCell,
+    Paragraph,
+    Strong,
+    Emphasis,
+    Annotation,
+    AnnotationNote,
+    Link { url: String },
+    WikiLink { target: String },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    Start(Tag),
+    End(Tag),
+    Text(String),
+    Code(String),
+    InlineMath(String),
+    DisplayMath(String),
+    Kbd(Vec<String>),
+    Time(String),
+    FootnoteReference(usize),
+    LinkCard { title: String, image: Option<String>, url: String, description: Option<String>, site_name: Option<String> },
+}
+
+pub fn events(content: &[Block]) -> Vec<Event> {
+    let mut events = Vec::new();
+    push_blocks(content, &mut events);
+    events
+}
+
+fn push_blocks(content: &[Block], events: &mut Vec<Event>) {
+    for block in content {
+        push_block(block, events);
+    }
+}
+
+fn push_block(block: &Block, events: &mut Vec<Event>) {
+    match block {
+        Block::Header { prims, level, id } => {
+            let tag = Tag::Heading { level: *level, id: id.clone() };
+            events.push(Event::Start(tag.clone()));
+            push_prims(prims, events);
+            events.push(Event::End(tag));
+        },
+        Block::Blockquote { lines, cite } => {
+            events.push(Event::Start(Tag::BlockQuote));
+            for line in lines {
+                wrap(Tag::Paragraph, line, events);
+            }
+            if let Some(cite) = cite {
+                wrap(Tag::Cite, cite, events);
+            }
+            events.push(Event::End(Tag::BlockQuote));
+        },
+        Block::ListElement(list) => push_list(list, events),
+        Block::Image { title, url, attrs } => {
+            let ImageAttrs { width, height, align } = attrs.clone();
+            wrap(Tag::Image { url: url.clone(), width, height, align }, title, events);
+        },
+        Block::Gallery { images } => {
+            events.push(Event::Start(Tag::Gallery));
+            push_blocks(images, events);
+            events.push(Event::End(Tag::Gallery));
+        },
+        Block::Columns { columns } => {
+            events.push(Event::Start(Tag::Columns));
+            for column in columns {
+                events.push(Event::Start(Tag::Column));
+                push_blocks(column, events);
+                events.push(Event::End(Tag::Column));
+            }
+            events.push(Event::End(Tag::Columns));
+        },
+        Block::Container { tag, open, summary, content } => {
+            let tag = Tag::Container { tag: tag.clone(), open: open.clone() };
+            events.push(Event::Start(tag.clone()));
+            if let Some(summary) = summary {
+                wrap(Tag::Summary, summary, events);
+            }
+            push_blocks(content, events);
+            events.push(Event::End(tag));
+        },
+        Block::LinkCard { title, image, url, description, site_name } => events.push(Event::LinkCard {
+            title: title.clone(),
+            image: image.clone(),
+            url: url.clone(),
+            description: description.clone(),
+            site_name: site_name.clone(),
+        }),
+        Block::MathBlock { math } => events.push(Event::DisplayMath(math.clone())),
+        Block::CodeBlock { lang, code } => {
+            let tag = Tag::CodeBlock { lang: lang.clone() };
+            events.push(Event::Start(tag.clone()));
+            events.push(Event::Text(code.clone()));
+            events.push(Event::End(tag));
+        },
+        Block::Table { caption, head, body } => {
+            events.push(Event::Start(Tag::Table));
+            if let Some(caption) = caption {
+                wrap(Tag::TableCaption, caption, events);
+            }
+            for (part, rows) in [(Tag::TableHead, head), (Tag::TableBody, body)] {
+                events.push(Event::Start(part.clone()));
+                for row in rows {
+                    events.push(Event::Start(Tag::TableRow));
+                    for cell in row {
+                        wrap(Tag::TableCell, cell, events);
+                    }
+                    events.push(Event::End(Tag::TableRow));
+                }
+                events.push(Event::End(part));
+            }
+            events.push(Event::End(Tag::Table));
+        },
+        Block::Paragraph { spans } => wrap(Tag::Paragraph, spans, events),
+    }
+}
+
+// items of a loose list get their text wrapped in a paragraph, as in the html output
+fn push_list(list: &List, events: &mut Vec<Event>) {
+    if list.items.is_empty() {
+        return;
+    }
+    let start = list.items[0].number.filter(|_| list.ordered);
+    let tag = Tag::List { ordered: list.ordered, start };
+    events.push(Event::Start(tag.clone()));
+    for item in &list.items {
+        let item_tag = Tag::Item { number: item.number, marker: item.marker };
+        events.push(Event::Start(item_tag.clone()));
+        if list.loose {
+            wrap(Tag::Paragraph, &item.spans, events);
+        } else {
+            push_spans(&item.spans, events);
+        }
+        push_list(&item.list, events);
+        events.push(Event::End(item_tag));
+    }
+    events.push(Event::End(tag));
+}
+
+fn wrap(tag: Tag, spans: &[Span], events: &mut Vec<Event>) {
+    events.push(Event::Start(tag.clone()));
+    push_spans(spans, events);
+    events.push(Event::End(tag));
+}
+
+fn push_spans(spans: &[Span], events: &mut Vec<Event>) {
+    for span in spans {
+        match span {
+            Span::Bold { text } => wrap(Tag::Strong, text, events),
+            Span::Ital { text } => wrap(Tag::Emphasis, text, events),
+            Span::Annotation { text, note } => {
+                events.push(Event::Start(Tag::Annotation));
+                push_spans(text, events);
+                wrap(Tag::AnnotationNote, note, events);
+                events.push(Event::End(Tag::Annotation));
+            },
+            Span::PrimElem(prim) => push_prim(prim, events),
+        }
+    }
+}
+
+fn push_prims(prims: &[Prim], events: &mut Vec<Event>) {
+    for prim in prims {
+        push_prim(prim, events);
+    }
+}
+
+fn push_prim(prim: &Prim, events: &mut Vec<Event>) {
+    match prim {
+        Prim::Link { text, url } => {
+            let tag = Tag::Link { url: url.clone() };
+            events.push(Event::Start(tag.clone()));
+            push_prims(text, events);
+            events.push(Event::End(tag));
+        },
+        Prim::WikiLink { target, label } => {
+            let tag = Tag::WikiLink { target: target.clone() };
+            events.push(Event::Start(tag.clone()));
+            events.push(Event::Text(label.clone()));
+            events.push(Event::End(tag));
+        },
+        Prim::Math { math } => events.push(Event::InlineMath(math.clone())),
+        Prim::Code { code } => events.push(Event::Code(code.clone())),
+        Prim::Kbd { keys } => events.push(Event::Kbd(keys.clone())),
+        Prim::FootnoteRef { id } => events.push(Event::FootnoteReference(*id)),
+        Prim::Time { date } => events.push(Event::Time(date.clone())),
+        Prim::Text { text } => events.push(Event::Text(text.clone())),
+    }
+}
+
+// the inverse of events, so a stream can be written back out through codegen
+pub fn blocks(events: &[Event]) -> Vec<Block> {
+    let mut reader = Reader { events, pos: 0 };
+    let mut content = Vec::new();
+    while reader.pos < events.len() {
+        content.extend(reader.read_blocks());
+        reader.pos += 1;
+    }
+    content
+}
+
+pub fn gen_events_html(events: &[Event], indent: usize, options: &RenderOptions) -> String {
+    gen_blocks_html(&blocks(events), indent, options).concat()
+}
+
+struct Reader<'a> {
+    events: &'a [Event],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn peek(&self) -> Option<&Event> {
+        self.events.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<&Event> {
+        self.pos += 1;
+        self.events.get(self.pos - 1)
+    }
+
+    // reads blocks up to the end tag of the enclosing block, leaving it unread
+    fn read_blocks(&mut self) -> Vec<Block> {
+        let mut content = Vec::new();
+        while let Some(event) = self.peek() {
+            if let Event::End(_) = event {
+                break;
+            }
+            if let Some(block) = self.read_block() {
+                content.push(block);
+            }
+        }
+        content
+    }
+
+    fn read_block(&mut self) -> Option<Block> {
+        let block = match self.next()?.clone() {
+            Event::Start(Tag::Heading { level, id }) => {
+                Block::Header { prims: self.read_prims(), level, id }
+            },
+            Event::Start(Tag::BlockQuote) => {
+                let mut lines = Vec::new();
+                let mut cite = None;
+                while let Some(Event::Start(tag)) = self.next() {
+                    match tag {
+                        Tag::Cite => cite = Some(self.read_inside()),
+                        _ => lines.push(self.read_inside()),
+                    }
+                }
+                Block::Blockquote { lines, cite }
+            },
+            Event::Start(Tag::List { ordered, .. }) => Block::ListElement(self.read_list(ordered)),
+            Event::Start(Tag::Image { url, width, height, align }) => {
+                let title = self.read_inside();
+                Block::Image { title, url, attrs: ImageAttrs { width, height, align } }
+            },
+            Event::Start(Tag::Gallery) => {
+                let images = self.read_blocks();
+                self.pos += 1;
+                Block::Gallery { images }
+            },
+            Event::Start(Tag::Columns) => {
+                let mut columns = Vec::new();
+                while let Some(Event::Start(_)) = self.next() {
+                    columns.push(self.read_blocks());
+                    self.pos += 1;
+                }
+                Block::Columns { columns }
+            },
+            Event::Start(Tag::Container { tag, open }) => {
+                let mut summary = None;
+                if let Some(Event::Start(Tag::Summary)) = self.peek() {
+                    self.pos += 1;
+                    summary = Some(self.read_inside());
+                }
+                let content = self.read_blocks();
+                self.pos += 1;
+                Block::Container { tag, open, summary, content }
+            },
+            Event::LinkCard { title, image, url, description, site_name } => Block::LinkCard { title, image, url, description, site_name },
+            Event::DisplayMath(math) => Block::MathBlock { math },
+            Event::Start(Tag::CodeBlock { lang }) => {
+                let mut code = String::new();
+                while let Some(Event::Text(text)) = self.next() {
+                    code.push_str(text);
+                }
+                Block::CodeBlock { lang, code }
+            },
+            Event::Start(Tag::Table) => self.read_table(),
+            Event::Start(Tag::Paragraph) => Block::Paragraph { spans: self.read_inside() },
+            _ => return None,
+        };
+        Some(block)
+    }
+
+    // the start tag has been read. a loose list is told apart by the paragraph around item text
+    fn read_list(&mut self, ordered: bool) -> List {
+        let mut list = List { ordered, loose: false, items: Vec::new() };
+        while let Some(Event::Start(Tag::Item { number, marker })) = self.next().cloned() {
+            let spans = match self.peek() {
+                Some(Event::Start(Tag::Paragraph)) => {
+                    list.loose = true;
+                    self.pos += 1;
+                    self.read_inside()
+                },
+                _ => self.read_spans(),
+            };
+            let sublist = match self.peek() {
+                Some(Event::Start(Tag::List { ordered, .. })) => {
+                    let ordered = *ordered;
+                    self.pos += 1;
+                    self.read_list(ordered)
+                },
+                _ => List { ordered: false, loose: false, items: Vec::new() },
+            };
+            self.pos += 1;
+            list.items.push(ListItem { spans, list: sublist, number, marker });
+        }
+        list
+    }
+
+    fn read_table(&mut self) -> Block {
+        let mut caption = None;
+        let mut head = Vec::new();
+        let mut body = Vec::new();
+        while let Some(Event::Start(tag)) = self.next().cloned() {
+            match tag {
+                Tag::TableCaption => caption = Some(self.read_inside()),
+                part => {
+                    let rows = if part == Tag::TableHead { &mut head } else { &mut body };
+                    while let Some(Event::Start(_)) = self.next() {
+                        let mut row = Vec::new();
+                        while let Some(Event::Start(_)) = self.next() {
+                            row.push(self.read_inside());
+                        }
+                        rows.push(row);
+                    }
+                },
+            }
+        }
+        Block::Table { caption, head, body }
+    }
+
+    // spans up to an end tag, which is consumed
+    fn read_inside(&mut self) -> Vec<Span> {
+        let spans = self.read_spans();
+        self.pos += 1;
+        spans
+    }
+
+    // spans up to the first event that is not inline, leaving it unread
+    fn read_spans(&mut self) -> Vec<Span> {
+        let mut spans = Vec::new();
+        while let Some(event) = self.next().cloned() {
+            let span = match event {
+                Event::Start(Tag::Strong) => Span::Bold { text: self.read_inside() },
+                Event::Start(Tag::Emphasis) => Span::Ital { text: self.read_inside() },
+                Event::Start(Tag::Annotation) => {
+                    let text = self.read_spans();
+                    self.pos += 1;
+                    let note = self.read_inside();
+                    self.pos += 1;
+                    Span::Annotation { text, note }
+                },
+                Event::Start(Tag::Link { url }) => Span::PrimElem(Prim::Link { text: self.read_prims(), url }),
+                Event::Start(Tag::WikiLink { target }) => {
+                    let label = match self.next() {
+                        Some(Event::Text(label)) => label.clone(),
+                        _ => String::new(),
+                    };
+                    self.pos += 1;
+                    Span::PrimElem(Prim::WikiLink { target, label })
+                },
+                Event::Text(text) => Span::PrimElem(Prim::Text { text }),
+                Event::Code(code) => Span::PrimElem(Prim::Code { code }),
+                Event::InlineMath(math) => Span::PrimElem(Prim::Math { math }),
+                Event::Kbd(keys) => Span::PrimElem(Prim::Kbd { keys }),
+                Event::FootnoteReference(id) => Span::PrimElem(Prim::FootnoteRef { id }),
+                Event::Time(date) => Span::PrimElem(Prim::Time { date }),
+                _ => {
+                    self.pos -= 1;
+                    break;
+                },
+            };
+            spans.push(span);
+        }
+        spans
+    }
+
+    // headings and link text hold only primaries
+    fn read_prims(&mut self) -> Vec<Prim> {
+        self.read_inside().into_iter().filter_map(|span| match span {
+            Span::PrimElem(prim) => Some(prim),
+            _ => None,
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse_markdown;
+
+    #[test]
+    fn events_write_back_the_same_html() {
+        let options = RenderOptions::default().with_offline(true);
+        let doc = parse_markdown(concat!(
+            "# Title\n\n",
+            "A **bold _mixed_** [link](https://a.example) with `code`, $x^2$, [[Wiki Page]] and ^[a note].\n\n",
+            "A word{>>with a _note_<<} and ++Ctrl+C++.\n\n",
+            "> quoted\n> -- [cite](https://a.example)\n\n",
+            "- tight\n  1. nested\n- items\n\nbetween\n\n",
+            "1. loose\n\n2. items\n\n",
+            "@[pic](a.png)\n\n",
+            "$$\ny = x\n$$\n\n",
+            "```rust\nfn main() {}\n```\n\n",
+            "Table: numbers\n| a | b |\n|---|---|\n| 1 | 2 |\n\n",
+            "::: columns\nleft\n---\nright\n:::\n\n",
+            "<details>\n<summary>More</summary>\nhidden\n</details>\n",
+        ), &options);
+        let expected = gen_blocks_html(&doc.content, 0, &options).concat();
+        assert_eq!(gen_events_html(&events(&doc.content), 0, &options), expected);
+        assert_eq!(events(&blocks(&events(&doc.content))), events(&doc.content));
+    }
+}
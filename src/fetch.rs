@@ -0,0 +1,161 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use regex::Regex;
+use reqwest::{self, header, Client, Url};
+use tokio::sync::{Mutex, Semaphore};
+use tokio::task::JoinSet;
+use tokio::time::{self, Instant};
+
+use crate::options::RenderOptions;
+
+#[derive(Clone, Debug, Default)]
+pub struct OgpInfo {
+    pub title: String,
+    pub image: Option<String>,
+    pub description: Option<String>,
+    pub site_name: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Fetch {
+    Title(String),
+    Ogp(String),
+}
+
+impl Fetch {
+    fn url(&self) -> &str {
+        match self {
+            Fetch::Title(url) | Fetch::Ogp(url) => url,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Fetched {
+    pub titles: HashMap<String, String>,
+    pub ogp: HashMap<String, OgpInfo>,
+}
+
+enum Response {
+    Title(String, String),
+    Ogp(String, OgpInfo),
+}
+
+struct Host {
+    permits: Semaphore,
+    next: Mutex<Instant>,
+}
+
+impl Host {
+    fn new(permits: usize) -> Self {
+        Host { permits: Semaphore::new(permits.max(1)), next: Mutex::new(Instant::now()) }
+    }
+
+    // space out the start of consecutive requests to this host by `delay`
+    async fn wait(&self, delay: Duration) {
+        let start = {
+            let mut next = self.next.lock().await;
+            let start = (*next).max(Instant::now());
+            *next = start + delay;
+            start
+        };
+        time::sleep_until(start).await;
+    }
+}
+
+#[tokio::main]
+pub async fn fetch_all(mut pending: Vec<Fetch>, options: &RenderOptions) -> Fetched {
+    let mut seen = HashSet::new();
+    pending.retain(|fetch| seen.insert(fetch.clone()));
+    pending.sort_by(|a, b| a.url().cmp(b.url()));
+
+    let client = options.client.clone().unwrap_or_default();
+    let total = Arc::new(Semaphore::new(options.max_concurrent_fetches.max(1)));
+    let mut hosts: HashMap<String, Arc<Host>> = HashMap::new();
+    let mut tasks = JoinSet::new();
+
+    for fetch in pending {
+        let host_name = Url::parse(fetch.url()).ok().and_then(|url| url.host_str().map(str::to_string)).unwrap_or_default();
+        let host = hosts.entry(host_name).or_insert_with(|| Arc::new(Host::new(options.max_fetches_per_host))).clone();
+        let total = total.clone();
+        let client = client.clone();
+        let delay = options.fetch_delay;
+        let timeout = options.fetch_timeout;
+
+        tasks.spawn(async move {
+            // the delay is counted from the global permit, so requests queued behind other hosts
+            // still start at least `delay` apart
+            let _host_permit = host.permits.acquire().await;
+            let _permit = total.acquire().await;
+            host.wait(delay).await;
+            match fetch {
+                Fetch::Title(url) => {
+                    let title = get_title(&client, &url, timeout).await;
+                    Response::Title(url, title)
+                },
+                Fetch::Ogp(url) => {
+                    let info = get_ogp_info(&client, &url, timeout).await;
+                    Response::Ogp(url, info)
+                },
+            }
+        });
+    }
+
+    let mut fetched = Fetched::default();
+    while let Some(res) = tasks.join_next().await {
+        match res {
+            Ok(Response::Title(url, title)) => { fetched.titles.insert(url, title); },
+            Ok(Response::Ogp(url, info)) => { fetched.ogp.insert(url, info); },
+            Err(_) => {},
+        }
+    }
+    fetched
+}
+
+async fn get_body(client: &Client, url: &str, timeout: Option<Duration>) -> Option<String> {
+    let mut req = client.get(url).header(header::ACCEPT, header::HeaderValue::from_str("text/html").unwrap());
+    if let Some(timeout) = timeout {
+        req = req.timeout(timeout);
+    }
+    let res = req.send().await.ok()?;
+    res.text().await.ok()
+}
+
+async fn get_title(client: &Client, url: &str, timeout: Option<Duration>) -> String {
+    let Some(body) = get_body(client, url, timeout).await else {
+        return String::new();
+    };
+    let regex = Regex::new("<title>(.*)</title>").unwrap();
+    if let Some(caps) = regex.captures(&body) {
+        return caps[1].to_string();
+    }
+    String::new()
+}
+
+async fn get_ogp_info(client: &Client, url: &str, timeout: Option<Duration>) -> OgpInfo {
+    let mut info = OgpInfo::default();
+    let Some(body) = get_body(client, url, timeout).await else {
+        return info;
+    };
+
+    let regex = Regex::new("property=\"og:([^\"]*)\" content=\"([^\"]*)\"").unwrap();
+    for caps in regex.captures_iter(&body) {
+        match &caps[1] {
+            "title" => { info.title = caps[2].to_string(); },
+            "image" => { info.image = Some(caps[2].to_string()); },
+            "description" => { info.description = Some(caps[2].to_string()); },
+            "site_name" => { info.site_name = Some(caps[2].to_string()); },
+            _ => {},
+        }
+    }
+
+    if info.title.is_empty() {
+        let regex = Regex::new("<title>(.*)</title>").unwrap();
+        if let Some(caps) = regex.captures(&body) {
+            info.title = caps[1].to_string();
+        }
+    }
+
+    info
+}
@@ -1,13 +1,21 @@
 pub mod data;
+pub mod fetch;
 pub mod multiset;
+pub mod options;
+pub mod outline;
 pub mod parser;
+pub mod slug;
 pub mod template;
+pub mod render;
 pub mod codegen;
+pub mod events;
+pub mod asciidoc;
 
 use std::env;
-use std::fs::{self, File};
+use std::fs::File;
 
-use crate::parser::parse_markdown;
+use crate::options::RenderOptions;
+use crate::parser::parse_file;
 use crate::template::read_template;
 use crate::codegen::gen_html;
 
@@ -16,14 +24,16 @@ fn main(){
     let temp_path = &args[1];
     let src_path = &args[2];
     let dest_path = &format!("{}.html", src_path.trim_end_matches(".md"));
-    let dest_path = if args.len() <= 3 { &dest_path } else { &args[3] };
+    let dest_path = if args.len() <= 3 { dest_path } else { &args[3] };
 
-    let Ok(doc) = fs::read_to_string(src_path) else {
+    let options = RenderOptions::default();
+    let Ok(document) = parse_file(src_path, &options) else {
         println!("could not open the source file.");
         return;
     };
-
-    let (title, toc, content) = parse_markdown(&doc);
+    for warning in &document.warnings {
+        println!("warning at byte {}: {}", warning.offset, warning.message);
+    }
 
     let Ok(temp) = read_template(temp_path) else {
         println!("could not open or read the template file.");
@@ -35,7 +45,7 @@ fn main(){
         return;
     };
     
-    let Ok(_) = gen_html(&mut dest, &title, &toc, &content, &temp) else {
+    let Ok(_) = gen_html(&mut dest, &document, &temp, &options) else {
         println!("could not write to the destination file.");
         return;
     };
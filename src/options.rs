@@ -0,0 +1,437 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, FixedOffset};
+use reqwest::Client;
+
+use crate::data::{Block, LinkCardData};
+use crate::fetch::OgpInfo;
+use crate::multiset::MultiSet;
+
+pub enum DateZone {
+    Local,
+    Utc,
+    Fixed(FixedOffset),
+}
+
+pub enum Newline {
+    Lf,
+    CrLf,
+}
+
+pub type SlugFn = Box<dyn Fn(&str) -> String + Send + Sync>;
+pub type WikiLinkResolver = Box<dyn Fn(&str) -> Option<String> + Send + Sync>;
+pub type UrlResolver = Box<dyn Fn(&str) -> String + Send + Sync>;
+pub type BlockHook = Box<dyn Fn(&Block, &str) -> String + Send + Sync>;
+pub type LinkCardRenderer = Box<dyn Fn(&LinkCardData, &mut dyn Write) -> io::Result<()> + Send + Sync>;
+
+pub struct RenderOptions {
+    // parser
+    pub toc_ordered: bool,
+    pub treat_h1_as_title: bool,
+    pub strict_math_delimiters: bool,
+    pub gallery_threshold: usize,
+    pub time_elements: bool,
+    pub normalize_code_spans: bool,
+    pub truncate_table_rows: bool,
+    pub promote_images: bool,
+    pub max_depth: usize,
+    pub conditions: Vec<String>,
+    pub slug: Option<SlugFn>,
+    pub id_prefix: Option<String>,
+    pub header_ids: Option<Arc<Mutex<MultiSet<String>>>>,
+    pub parallel: bool,
+
+    // link metadata fetching
+    pub offline: bool,
+    pub metadata: HashMap<String, OgpInfo>,
+    pub max_concurrent_fetches: usize,
+    pub max_fetches_per_host: usize,
+    pub fetch_delay: Duration,
+    pub fetch_timeout: Option<Duration>,
+    pub client: Option<Client>,
+
+    // codegen
+    pub datetime: Option<DateTime<FixedOffset>>,
+    pub timezone: Option<DateZone>,
+    pub base_url: Option<String>,
+    pub url_resolver: Option<UrlResolver>,
+    pub sanitize: bool,
+    pub allowed_schemes: Vec<String>,
+    pub obfuscate_emails: bool,
+    pub mathml: bool,
+    pub math_noscript: bool,
+    pub number_equations: bool,
+    pub section_wrapping: bool,
+    pub flush_blocks: bool,
+    pub copy_button: bool,
+    pub tab_width: Option<usize>,
+    pub newline: Option<Newline>,
+    pub preserve_code_newlines: bool,
+    pub lead_class: Option<String>,
+    pub tags_class: String,
+    pub tag_url: Option<String>,
+    pub toc_list_class: Option<String>,
+    pub toc_item_class: Option<String>,
+    pub toc_link_class: Option<String>,
+    pub toc_collapse: Option<(usize, String)>,
+    pub toc_aria_label: Option<String>,
+    pub mini_toc: bool,
+    pub footnote_symbols: Vec<String>,
+    pub content_wrapper: Option<String>,
+    pub content_wrapper_class: Option<String>,
+    pub wrap_empty_content: bool,
+    pub back_to_top: Option<String>,
+    pub back_to_top_target: String,
+    pub block_ids: bool,
+    pub list_markers: bool,
+    pub lang_aliases: HashMap<String, String>,
+    pub code_class_prefix: String,
+    pub wiki_link_resolver: Option<WikiLinkResolver>,
+    pub link_card_renderer: Option<LinkCardRenderer>,
+    pub block_hook: Option<BlockHook>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            toc_ordered: true,
+            treat_h1_as_title: true,
+            strict_math_delimiters: false,
+            gallery_threshold: 2,
+            time_elements: false,
+            normalize_code_spans: true,
+            truncate_table_rows: true,
+            promote_images: false,
+            max_depth: 64,
+            conditions: Vec::new(),
+            slug: None,
+            id_prefix: None,
+            header_ids: None,
+            parallel: false,
+            offline: false,
+            metadata: HashMap::new(),
+            max_concurrent_fetches: 8,
+            max_fetches_per_host: 2,
+            fetch_delay: Duration::ZERO,
+            fetch_timeout: None,
+            client: None,
+            datetime: None,
+            timezone: None,
+            base_url: None,
+            url_resolver: None,
+            sanitize: false,
+            obfuscate_emails: false,
+            allowed_schemes: vec![String::from("http"), String::from("https"), String::from("mailto")],
+            mathml: false,
+            math_noscript: false,
+            number_equations: false,
+            section_wrapping: false,
+            flush_blocks: false,
+            copy_button: false,
+            tab_width: None,
+            newline: None,
+            preserve_code_newlines: false,
+            lead_class: None,
+            tags_class: String::from("tags"),
+            tag_url: None,
+            toc_list_class: None,
+            toc_item_class: None,
+            toc_link_class: None,
+            toc_collapse: None,
+            toc_aria_label: None,
+            mini_toc: false,
+            footnote_symbols: Vec::new(),
+            content_wrapper: None,
+            content_wrapper_class: None,
+            wrap_empty_content: true,
+            back_to_top: None,
+            back_to_top_target: String::from("#"),
+            block_ids: false,
+            list_markers: false,
+            lang_aliases: HashMap::new(),
+            code_class_prefix: String::from("language-"),
+            wiki_link_resolver: None,
+            link_card_renderer: None,
+            block_hook: None,
+        }
+    }
+}
+
+impl RenderOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_toc_ordered(mut self, toc_ordered: bool) -> Self {
+        self.toc_ordered = toc_ordered;
+        self
+    }
+
+    pub fn with_treat_h1_as_title(mut self, treat_h1_as_title: bool) -> Self {
+        self.treat_h1_as_title = treat_h1_as_title;
+        self
+    }
+
+    pub fn with_strict_math_delimiters(mut self, strict: bool) -> Self {
+        self.strict_math_delimiters = strict;
+        self
+    }
+
+    pub fn with_gallery_threshold(mut self, threshold: usize) -> Self {
+        self.gallery_threshold = threshold;
+        self
+    }
+
+    pub fn with_time_elements(mut self, time_elements: bool) -> Self {
+        self.time_elements = time_elements;
+        self
+    }
+
+    pub fn with_normalize_code_spans(mut self, normalize: bool) -> Self {
+        self.normalize_code_spans = normalize;
+        self
+    }
+
+    pub fn with_truncate_table_rows(mut self, truncate: bool) -> Self {
+        self.truncate_table_rows = truncate;
+        self
+    }
+
+    pub fn with_promote_images(mut self, promote: bool) -> Self {
+        self.promote_images = promote;
+        self
+    }
+
+    pub fn with_max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    pub fn with_conditions<S: Into<String>>(mut self, conditions: impl IntoIterator<Item = S>) -> Self {
+        self.conditions = conditions.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_slug(mut self, slug: impl Fn(&str) -> String + Send + Sync + 'static) -> Self {
+        self.slug = Some(Box::new(slug));
+        self
+    }
+
+    pub fn with_id_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.id_prefix = Some(prefix.into());
+        self
+    }
+
+    pub fn with_header_ids(mut self, ids: Arc<Mutex<MultiSet<String>>>) -> Self {
+        self.header_ids = Some(ids);
+        self
+    }
+
+    pub fn with_parallel(mut self, parallel: bool) -> Self {
+        self.parallel = parallel;
+        self
+    }
+
+    pub fn with_offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    pub fn with_metadata(mut self, metadata: HashMap<String, OgpInfo>) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    pub fn with_fetch_limits(mut self, max_concurrent: usize, max_per_host: usize, delay: Duration) -> Self {
+        self.max_concurrent_fetches = max_concurrent;
+        self.max_fetches_per_host = max_per_host;
+        self.fetch_delay = delay;
+        self
+    }
+
+    pub fn with_fetch_timeout(mut self, timeout: Duration) -> Self {
+        self.fetch_timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.client = Some(client);
+        self
+    }
+
+    pub fn with_datetime(mut self, datetime: DateTime<FixedOffset>) -> Self {
+        self.datetime = Some(datetime);
+        self
+    }
+
+    pub fn with_timezone(mut self, timezone: DateZone) -> Self {
+        self.timezone = Some(timezone);
+        self
+    }
+
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = Some(base_url.into());
+        self
+    }
+
+    pub fn with_url_resolver(mut self, resolver: impl Fn(&str) -> String + Send + Sync + 'static) -> Self {
+        self.url_resolver = Some(Box::new(resolver));
+        self
+    }
+
+    pub fn with_sanitize(mut self, sanitize: bool) -> Self {
+        self.sanitize = sanitize;
+        self
+    }
+
+    pub fn with_allowed_schemes<S: Into<String>>(mut self, schemes: impl IntoIterator<Item = S>) -> Self {
+        self.allowed_schemes = schemes.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_obfuscate_emails(mut self, obfuscate: bool) -> Self {
+        self.obfuscate_emails = obfuscate;
+        self
+    }
+
+    pub fn with_mathml(mut self, mathml: bool) -> Self {
+        self.mathml = mathml;
+        self
+    }
+
+    pub fn with_math_noscript(mut self, noscript: bool) -> Self {
+        self.math_noscript = noscript;
+        self
+    }
+
+    pub fn with_number_equations(mut self, number: bool) -> Self {
+        self.number_equations = number;
+        self
+    }
+
+    pub fn with_section_wrapping(mut self, section_wrapping: bool) -> Self {
+        self.section_wrapping = section_wrapping;
+        self
+    }
+
+    pub fn with_flush_blocks(mut self, flush: bool) -> Self {
+        self.flush_blocks = flush;
+        self
+    }
+
+    pub fn with_copy_button(mut self, copy_button: bool) -> Self {
+        self.copy_button = copy_button;
+        self
+    }
+
+    pub fn with_tab_width(mut self, width: usize) -> Self {
+        self.tab_width = Some(width);
+        self
+    }
+
+    pub fn with_newline(mut self, newline: Newline) -> Self {
+        self.newline = Some(newline);
+        self
+    }
+
+    pub fn with_preserve_code_newlines(mut self, preserve: bool) -> Self {
+        self.preserve_code_newlines = preserve;
+        self
+    }
+
+    pub fn with_lead_class(mut self, class: impl Into<String>) -> Self {
+        self.lead_class = Some(class.into());
+        self
+    }
+
+    pub fn with_tags_class(mut self, class: impl Into<String>) -> Self {
+        self.tags_class = class.into();
+        self
+    }
+
+    pub fn with_tag_url(mut self, url: impl Into<String>) -> Self {
+        self.tag_url = Some(url.into());
+        self
+    }
+
+    pub fn with_toc_classes(mut self, list: impl Into<String>, item: impl Into<String>, link: impl Into<String>) -> Self {
+        self.toc_list_class = Some(list.into());
+        self.toc_item_class = Some(item.into());
+        self.toc_link_class = Some(link.into());
+        self
+    }
+
+    pub fn with_toc_collapse(mut self, depth: usize, summary: impl Into<String>) -> Self {
+        self.toc_collapse = Some((depth, summary.into()));
+        self
+    }
+
+    pub fn with_toc_aria_label(mut self, label: impl Into<String>) -> Self {
+        self.toc_aria_label = Some(label.into());
+        self
+    }
+
+    pub fn with_mini_toc(mut self, mini_toc: bool) -> Self {
+        self.mini_toc = mini_toc;
+        self
+    }
+
+    pub fn with_footnote_symbols<S: Into<String>>(mut self, symbols: impl IntoIterator<Item = S>) -> Self {
+        self.footnote_symbols = symbols.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn with_content_wrapper(mut self, tag: impl Into<String>, class: Option<&str>) -> Self {
+        self.content_wrapper = Some(tag.into());
+        self.content_wrapper_class = class.map(str::to_string);
+        self
+    }
+
+    pub fn with_wrap_empty_content(mut self, wrap: bool) -> Self {
+        self.wrap_empty_content = wrap;
+        self
+    }
+
+    pub fn with_back_to_top(mut self, text: impl Into<String>, target: impl Into<String>) -> Self {
+        self.back_to_top = Some(text.into());
+        self.back_to_top_target = target.into();
+        self
+    }
+
+    pub fn with_block_ids(mut self, block_ids: bool) -> Self {
+        self.block_ids = block_ids;
+        self
+    }
+
+    pub fn with_list_markers(mut self, list_markers: bool) -> Self {
+        self.list_markers = list_markers;
+        self
+    }
+
+    pub fn with_lang_alias(mut self, alias: impl Into<String>, lang: impl Into<String>) -> Self {
+        self.lang_aliases.insert(alias.into(), lang.into());
+        self
+    }
+
+    pub fn with_code_class_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.code_class_prefix = prefix.into();
+        self
+    }
+
+    pub fn with_wiki_link_resolver(mut self, resolver: impl Fn(&str) -> Option<String> + Send + Sync + 'static) -> Self {
+        self.wiki_link_resolver = Some(Box::new(resolver));
+        self
+    }
+
+    pub fn with_link_card_renderer(mut self, renderer: impl Fn(&LinkCardData, &mut dyn Write) -> io::Result<()> + Send + Sync + 'static) -> Self {
+        self.link_card_renderer = Some(Box::new(renderer));
+        self
+    }
+
+    pub fn with_block_hook(mut self, hook: impl Fn(&Block, &str) -> String + Send + Sync + 'static) -> Self {
+        self.block_hook = Some(Box::new(hook));
+        self
+    }
+}
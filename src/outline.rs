@@ -0,0 +1,73 @@
+use crate::codegen::{gen_prim_text, gen_text};
+use crate::data::Block;
+
+#[derive(Debug)]
+pub struct OutlineNode {
+    pub level: u32,
+    pub id: String,
+    pub text: String,
+    pub children: Vec<OutlineNode>,
+}
+
+pub fn outline(content: &[Block]) -> Vec<OutlineNode> {
+    let mut roots: Vec<OutlineNode> = Vec::new();
+    for block in content {
+        let Block::Header { prims, level, id } = block else {
+            continue;
+        };
+
+        // descend while the last node is shallower, so skipped levels attach to the nearest one
+        let mut cur = &mut roots;
+        while cur.last().is_some_and(|node| node.level < *level) {
+            cur = &mut cur.last_mut().unwrap().children;
+        }
+        cur.push(OutlineNode {
+            level: *level,
+            id: id.clone(),
+            text: prims.iter().map(gen_prim_text).collect(),
+            children: Vec::new(),
+        });
+    }
+    roots
+}
+
+// the ids given to images and tables by the `block_ids` option, numbered per type in document order
+#[derive(Debug)]
+pub struct BlockAnchor {
+    pub id: String,
+    pub caption: String,
+}
+
+pub fn block_anchors(content: &[Block]) -> Vec<BlockAnchor> {
+    let mut anchors = Vec::new();
+    collect_anchors(content, &mut anchors, &mut 0, &mut 0);
+    anchors
+}
+
+fn collect_anchors(content: &[Block], anchors: &mut Vec<BlockAnchor>, figures: &mut usize, tables: &mut usize) {
+    for block in content {
+        match block {
+            Block::Image { title, .. } => {
+                *figures += 1;
+                anchors.push(BlockAnchor {
+                    id: format!("fig-{}", figures),
+                    caption: gen_text(title),
+                });
+            },
+            Block::Table { caption, .. } => {
+                *tables += 1;
+                anchors.push(BlockAnchor {
+                    id: format!("table-{}", tables),
+                    caption: caption.as_ref().map(gen_text).unwrap_or_default(),
+                });
+            },
+            Block::Gallery { images } | Block::Container { content: images, .. } => collect_anchors(images, anchors, figures, tables),
+            Block::Columns { columns } => {
+                for column in columns {
+                    collect_anchors(column, anchors, figures, tables);
+                }
+            },
+            _ => {},
+        }
+    }
+}
@@ -1,67 +1,404 @@
-use tokio;
-use regex::Regex;
-use reqwest::{self, header};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::PoisonError;
+use chrono::NaiveDate;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
 
+use crate::codegen::{escape_bare_ampersands, gen_text};
 use crate::data::*;
+use crate::fetch::{fetch_all, Fetch, Fetched, OgpInfo};
 use crate::multiset::MultiSet;
+use crate::options::RenderOptions;
 use Block::*;
 use Span::*;
 use Prim::*;
 
-pub fn parse_markdown(doc: &str) -> (String, List, Vec<Block>) {
-    let mut parser = Parser::new(doc);
+pub fn parse_markdown(doc: &str, options: &RenderOptions) -> Document {
+    let parser = parse(doc, options, false);
+    Document {
+        title: parser.title,
+        toc: parser.toc,
+        content: parser.content,
+        ranges: parser.ranges,
+        footnotes: parser.footnotes,
+        front_matter: parser.front_matter,
+        warnings: parser.warnings,
+    }
+}
+
+pub fn parse_file(path: impl AsRef<Path>, options: &RenderOptions) -> io::Result<Document> {
+    let doc = fs::read_to_string(path)?;
+    Ok(parse_markdown(&doc, options))
+}
+
+// parse blocks only, without front matter, title or table of contents
+pub fn parse_fragment(doc: &str, options: &RenderOptions) -> Vec<Block> {
+    parse(doc, options, true).content
+}
+
+// apply an edit replacing `edit` in `source` with `text`, re-parsing only the blank-line
+// delimited region around it when that cannot affect other blocks, and return the new
+// source with the indices of the blocks that changed
+pub fn reparse(doc: &mut Document, source: &str, edit: Range<usize>, text: &str, options: &RenderOptions) -> (String, Range<usize>) {
+    let new_source = format!("{}{}{}", &source[..edit.start], text, &source[edit.end..]);
+    let full = |doc: &mut Document, new_source: String| {
+        *doc = parse_markdown(&new_source, options);
+        let len = doc.content.len();
+        (new_source, 0..len)
+    };
+
+    // widen the region to whole paragraphs, then to every block it touches
+    let mut start = source[..edit.start].rfind("\n\n").map_or(0, |i| i + 2);
+    let mut end = source[edit.end..].find("\n\n").map_or(source.len(), |i| edit.end + i + 1);
+    let first = doc.ranges.iter().position(|range| range.end > start).unwrap_or(doc.ranges.len());
+    let last = doc.ranges.iter().rposition(|range| range.start < end).map_or(first, |i| i + 1).max(first);
+    if first < last {
+        start = start.min(doc.ranges[first].start);
+        end = end.max(doc.ranges[last - 1].end);
+    }
+
+    // headers, footnotes, images, lists, fences and html or ::: containers depend on their neighbours or on document-wide state
+    let new_end = end + text.len() - edit.len();
+    let region = &new_source[start..new_end];
+    let global = |text: &str| {
+        text.lines().any(|line| line.starts_with(['#', ':', '<']) || line.starts_with("---"))
+            || ["^[", "@[", "```", "$$", "\\[", "<!--"].iter().any(|pattern| text.contains(pattern))
+            || (options.promote_images && text.contains("!["))
+    };
+    let joins = |i: Option<usize>| i.and_then(|i| doc.content.get(i)).is_some_and(|block| matches!(block, Image { .. } | Gallery { .. } | ListElement(_)));
+    if start == 0 || global(&source[start..end]) || global(region) || joins(first.checked_sub(1)) || joins(Some(last)) {
+        return full(doc, new_source);
+    }
+
+    let parser = parse(region, options, true);
+    let delta = new_end as isize - end as isize;
+    let ranges = parser.ranges.into_iter().map(|range| range.start + start..range.end + start);
+    let after = doc.ranges.split_off(last).into_iter().map(|range| (range.start as isize + delta) as usize..(range.end as isize + delta) as usize);
+    doc.ranges.truncate(first);
+    doc.ranges.extend(ranges.chain(after));
+    let count = parser.content.len();
+    doc.content.splice(first..last, parser.content);
+
+    // warnings in the region are replaced by those of the re-parse
+    doc.warnings.retain(|warning| warning.offset < start || warning.offset >= end);
+    for warning in &mut doc.warnings {
+        if warning.offset >= end {
+            warning.offset = (warning.offset as isize + delta) as usize;
+        }
+    }
+    doc.warnings.extend(parser.warnings.into_iter().map(|warning| Warning { offset: warning.offset + start, message: warning.message }));
+    doc.warnings.sort_by_key(|warning| warning.offset);
+
+    (new_source, first..first + count)
+}
+
+fn parse<'a>(doc: &'a str, options: &'a RenderOptions, fragment: bool) -> Parser<'a> {
+    let parse_with = |headers: &MultiSet<String>, fetched: Fetched| if options.parallel && !fragment {
+        parse_parallel(doc, options, headers, fetched)
+    } else {
+        parse_sequential(doc, options, headers, fetched, fragment)
+    };
+    // ids shared with other documents stay locked while ids are assigned, so that documents parsed
+    // at the same time cannot take the same id, but not while fetching. a poisoned lock still
+    // holds a usable set. fragments avoid the shared ids but do not add to them
+    let lock = || options.header_ids.as_ref().map(|ids| ids.lock().unwrap_or_else(PoisonError::into_inner));
+    let mut shared = lock();
+    let mut parser = parse_with(&shared.as_deref().cloned().unwrap_or_default(), Fetched::default());
+
+    // fetched titles can change header ids and the toc, so parse again once they are known
+    if !parser.pending.is_empty() && !options.offline {
+        drop(shared);
+        let fetched = fetch_all(std::mem::take(&mut parser.pending), options);
+        shared = lock();
+        parser = parse_with(&shared.as_deref().cloned().unwrap_or_default(), fetched);
+    }
+
+    // only the final pass counts towards the shared ids
+    if let Some(shared) = shared.as_mut().filter(|_| !fragment) {
+        **shared = parser.headers.clone();
+    }
+    parser
+}
+
+fn parse_sequential<'a>(doc: &'a str, options: &'a RenderOptions, headers: &MultiSet<String>, fetched: Fetched, fragment: bool) -> Parser<'a> {
+    let mut parser = Parser::new(doc, options, headers.clone(), fetched, fragment);
     parser.parse_markdown();
-    return (parser.title, parser.toc, parser.content);
+    parser
+}
+
+// sections starting at top-level headers are parsed in parallel, then their headers and
+// footnotes are numbered in document order as the sections are joined
+#[cfg(feature = "parallel")]
+fn parse_parallel<'a>(doc: &'a str, options: &'a RenderOptions, headers: &MultiSet<String>, fetched: Fetched) -> Parser<'a> {
+    let mut parser = Parser::new(doc, options, headers.clone(), fetched, false);
+    parser.parse_head();
+    let sections: Vec<Parser<'a>> = split_sections(parser.chs).par_iter().map(|&(source, level)| {
+        let mut section = Parser::new(doc, options, MultiSet::new(), parser.fetched.clone(), false);
+        section.chs = source;
+        section.level = level;
+        section.deferred = true;
+        (section.content, section.ranges) = section.parse_ranged_blocks().into_iter().unzip();
+        section
+    }).collect();
+    for mut section in sections {
+        parser.pending.append(&mut section.pending);
+        parser.join(section);
+    }
+    parser.promote_images();
+    parser.group_galleries();
+    parser.merge_texts();
+    parser
+}
+
+#[cfg(not(feature = "parallel"))]
+fn parse_parallel<'a>(doc: &'a str, options: &'a RenderOptions, headers: &MultiSet<String>, fetched: Fetched) -> Parser<'a> {
+    parse_sequential(doc, options, headers, fetched, false)
 }
 
 pub struct Parser<'a> {
+    doc: &'a str,
     chs: &'a str,
+    options: &'a RenderOptions,
+    fetched: Fetched,
+    fragment: bool,
+    pending: Vec<Fetch>,
     headers: MultiSet<String>,
+    front_matter: BTreeMap<String, String>,
     title: String,
+    titled: bool,
     toc: List,
+    toc_h1: bool,
     content: Vec<Block>,
+    ranges: Vec<Range<usize>>,
+    footnotes: Vec<Vec<Span>>,
+    level: u32,
+    depth: usize,
+    too_deep: bool,
+    misses: Vec<(&'static str, Range<usize>, usize)>,
+    code_misses: Vec<(usize, Range<usize>, usize)>,
+    closer_misses: Vec<(&'static str, Range<usize>, usize)>,
+    line: Option<(Range<usize>, usize)>,
+    brackets: Option<(Range<usize>, usize, HashMap<usize, usize>)>,
+    fences: Vec<(&'static str, Range<usize>, HashMap<usize, usize>)>,
+    warnings: Vec<Warning>,
+    deferred: bool,
+    deferred_headers: Vec<(Vec<Prim>, String)>,
+    after_paragraph: bool,
 }
 
 impl<'a> Parser<'a> {
-    fn new(doc: &'a str) -> Self {
+    fn new(doc: &'a str, options: &'a RenderOptions, headers: MultiSet<String>, fetched: Fetched, fragment: bool) -> Self {
+        // a leading byte order mark from windows editors would hide the first header,
+        // and offsets stay relative to the document including it
         Parser {
-            chs: doc,
-            headers: MultiSet::new(),
+            doc,
+            chs: doc.strip_prefix('\u{feff}').unwrap_or(doc),
+            options,
+            fetched,
+            fragment,
+            pending: Vec::new(),
+            headers,
+            front_matter: BTreeMap::new(),
             title: String::new(),
-            toc: List { ordered: true, items: Vec::new() },
+            titled: false,
+            toc: List { ordered: options.toc_ordered, loose: false, items: Vec::new() },
+            toc_h1: false,
             content: Vec::new(),
+            ranges: Vec::new(),
+            footnotes: Vec::new(),
+            level: 0,
+            depth: 0,
+            too_deep: false,
+            misses: Vec::new(),
+            code_misses: Vec::new(),
+            closer_misses: Vec::new(),
+            line: None,
+            brackets: None,
+            fences: Vec::new(),
+            warnings: Vec::new(),
+            deferred: false,
+            deferred_headers: Vec::new(),
+            after_paragraph: false,
         }
     }
 
     pub fn parse_markdown(&mut self) {
+        self.parse_head();
+        (self.content, self.ranges) = self.parse_ranged_blocks().into_iter().unzip();
+        self.promote_images();
+        self.group_galleries();
+        self.merge_texts();
+    }
+
+    fn parse_head(&mut self) {
+        if !self.fragment {
+            self.parse_front_matter();
+        }
+        if !self.options.treat_h1_as_title {
+            if let Some(title) = self.front_matter.get("title") {
+                self.title = title.chars().map(|c| self.escape(c)).collect();
+            }
+        }
+    }
+
+    // append a section parsed on its own, registering its headers as if they had been parsed here
+    #[cfg(feature = "parallel")]
+    fn join(&mut self, mut section: Parser<'a>) {
+        let base = self.footnotes.len();
+        let mut headers = Vec::new();
+        if base > 0 {
+            for block in &mut section.content {
+                shift_footnotes(block, base);
+            }
+            for note in &mut section.footnotes {
+                shift_span_footnotes(note, base);
+            }
+        }
+        collect_headers(&mut section.content, &mut headers);
+        for (header, (mut toc, text)) in headers.into_iter().zip(section.deferred_headers) {
+            shift_prim_footnotes(&mut toc, base);
+            if let Header { level, id, .. } = header {
+                *id = self.register_header(toc, text, std::mem::take(id), *level);
+            }
+        }
+        self.content.append(&mut section.content);
+        self.ranges.append(&mut section.ranges);
+        self.footnotes.append(&mut section.footnotes);
+        self.warnings.append(&mut section.warnings);
+    }
+
+    // join adjacent text nodes left behind by delimiters that turned out to be plain text
+    fn merge_texts(&mut self) {
+        for block in &mut self.content {
+            merge_block_texts(block);
+        }
+        for note in &mut self.footnotes {
+            merge_spans(note);
+        }
+        merge_list_texts(&mut self.toc);
+    }
+
+    fn parse_blocks(&mut self) -> Vec<Block> {
+        self.parse_ranged_blocks().into_iter().map(|(block, _)| block).collect()
+    }
+
+    // each block with the byte range of the source it was parsed from
+    fn parse_ranged_blocks(&mut self) -> Vec<(Block, Range<usize>)> {
+        let mut blocks = Vec::new();
+        self.after_paragraph = false;
         while !self.chs.is_empty() {
+            // blank lines only separate blocks; every block ends at the end of its last line
+            if self.skip_blank_line() {
+                self.after_paragraph = false;
+                continue;
+            }
+            if self.skip_comment() {
+                continue;
+            }
+            if let Some(mut kept) = self.parse_condition() {
+                blocks.append(&mut kept);
+                continue;
+            }
+            let start = self.offset();
             let block = self.parse_block();
+            self.after_paragraph = matches!(block, Paragraph { .. });
             match block {
-                Paragraph { spans } if spans.is_empty() => {},
-                _ => { self.content.push(block); },
+                Paragraph { spans } if spans.iter().all(is_blank) => {},
+                _ => { blocks.push((block, start..self.offset())); },
             }
         }
+        blocks
     }
 
-    fn parse_block(&mut self) -> Block {
-        // header
-        if self.starts_with_next("# ") {
-            return self.parse_header(1);
+    // a paragraph holding nothing but `![alt](url)` becomes an image block, before galleries are grouped
+    fn promote_images(&mut self) {
+        if !self.options.promote_images {
+            return;
         }
-        if self.starts_with_next("## ") {
-            return self.parse_header(2);
+        for block in &mut self.content {
+            let Paragraph { spans } = block else {
+                continue;
+            };
+            let mut prims = spans.iter_mut().filter(|span| !is_blank(span));
+            let (Some(PrimElem(Text { text: bang })), Some(PrimElem(Link { text, url })), None) = (prims.next(), prims.next(), prims.next()) else {
+                continue;
+            };
+            if bang.trim() != "!" {
+                continue;
+            }
+            let title = std::mem::take(text).into_iter().map(PrimElem).collect();
+            *block = Image { title, url: std::mem::take(url), attrs: ImageAttrs::default() };
         }
-        if self.starts_with_next("### ") {
-            return self.parse_header(3);
+    }
+
+    // replace runs of adjacent images that reach the threshold with a single gallery
+    fn group_galleries(&mut self) {
+        let threshold = self.options.gallery_threshold.max(2);
+        let mut content = Vec::new();
+        let mut run = Vec::new();
+        let blocks = std::mem::take(&mut self.content).into_iter().zip(std::mem::take(&mut self.ranges));
+        for (block, range) in blocks {
+            if let Image { .. } = block {
+                run.push((block, range));
+            } else {
+                push_images(&mut content, &mut run, threshold);
+                content.push((block, range));
+            }
         }
-        if self.starts_with_next("#### ") {
-            return self.parse_header(4);
+        push_images(&mut content, &mut run, threshold);
+        (self.content, self.ranges) = content.into_iter().unzip();
+    }
+
+    fn parse_front_matter(&mut self) {
+        let chs = self.chs;
+        if !(self.starts_with_next("---") && self.starts_with_newline_next()) {
+            self.chs = chs;
+            return;
         }
-        if self.starts_with_next("##### ") {
-            return self.parse_header(5);
+
+        // indented keys under a key with no value are nested, e.g. `author.name`
+        let mut parent: Option<String> = None;
+        while !self.chs.is_empty() {
+            let mut line = String::new();
+            while let Some(c) = self.next_char_until_newline() {
+                line.push(c);
+            }
+            if line.trim_end() == "---" {
+                return;
+            }
+            if let Some((key, value)) = line.split_once(':') {
+                let nested = line.starts_with([' ', '\t']);
+                let key = key.trim();
+                let value = value.trim().trim_matches('"');
+                let key = match &parent {
+                    Some(parent) if nested => format!("{}.{}", parent, key),
+                    _ => key.to_string(),
+                };
+                if value.is_empty() {
+                    parent = Some(key);
+                } else {
+                    if !nested {
+                        parent = None;
+                    }
+                    self.front_matter.insert(key, value.to_string());
+                }
+            }
         }
-        if self.starts_with_next("###### ") {
-            return self.parse_header(6);
+
+        // no closing delimiter, so this was not front matter
+        self.chs = chs;
+        self.front_matter.clear();
+    }
+
+    fn parse_block(&mut self) -> Block {
+        // header
+        let hashes = self.chs.len() - self.chs.trim_start_matches('#').len();
+        if (1..=6).contains(&hashes) && self.chs[hashes..].starts_with([' ', '\t']) {
+            return self.parse_atx_header(hashes);
         }
 
         // blockquote
@@ -69,19 +406,44 @@ impl<'a> Parser<'a> {
             return self.parse_blockquote();
         }
 
-        // list
-        if self.chs.starts_with("+ ") || self.chs.starts_with("- ") {
+        // list. a number right after a paragraph line, as in a sentence wrapped before `1999. `, stays text
+        if self.chs.starts_with("+ ") || self.chs.starts_with("- ") || (!self.after_paragraph && self.list_number().is_some()) {
             return ListElement(self.parse_list(0));
         }
 
         // embed
-        if self.starts_with_next("@[") {
+        if self.chs.starts_with("@[") && self.matching_bracket(1).is_some_and(|end| self.chs[end + 1..].starts_with('(')) {
             return self.parse_embed();
         }
 
+        // html container with markdown inside
+        let line = self.line();
+        if let Some(tag) = CONTAINER_TAGS.iter().find(|tag| open_tag(line, tag).is_some()) {
+            if self.enter_nesting() {
+                let container = self.parse_container(tag);
+                self.depth -= 1;
+                if let Some(container) = container {
+                    return container;
+                }
+            }
+        }
+
+        // columns
+        if line.trim_end() == "::: columns" && self.enter_nesting() {
+            let columns = self.parse_columns();
+            self.depth -= 1;
+            if let Some(columns) = columns {
+                return columns;
+            }
+        }
+
         // math block
         if self.starts_with_next("$$") {
-            return self.parse_math_block();
+            return self.parse_math_block("$$");
+        }
+        if self.chs.starts_with("\\[") && self.find_ahead(2, "\\]", false).is_some() {
+            self.chs = &self.chs[2..];
+            return self.parse_math_block("\\]");
         }
 
         // code block
@@ -89,13 +451,36 @@ impl<'a> Parser<'a> {
             return self.parse_code_block();
         }
 
-        // table
+        // table, optionally preceded by a caption line
+        if self.chs.starts_with("Table: ") && self.chs.lines().nth(1).is_some_and(|line| line.starts_with('|')) {
+            self.chs = &self.chs["Table: ".len()..];
+            let caption = self.parse_spans();
+            return self.parse_table(Some(caption));
+        }
         if self.chs.starts_with("|") {
-            return self.parse_table();
+            return self.parse_table(None);
+        }
+
+        // indented code block
+        if self.chs.starts_with("    ") || self.chs.starts_with("\t") {
+            return self.parse_indented_code_block();
         }
 
         // paragraph
-        return self.parse_paragraph();
+        self.parse_paragraph()
+    }
+
+    // any spaces or tabs after the hashes and an optional closing run of hashes are dropped
+    fn parse_atx_header(&mut self, hashes: usize) -> Block {
+        let end = self.chs.find('\n').unwrap_or(self.chs.len());
+        let line = self.chs[hashes..end].trim();
+        let open = line.trim_end_matches('#');
+        let text = if open.is_empty() || open.ends_with([' ', '\t']) { open.trim_end() } else { line };
+        let rest = &self.chs[(end + 1).min(self.chs.len())..];
+        self.chs = text;
+        let header = self.parse_header(hashes as u32);
+        self.chs = rest;
+        header
     }
 
     fn parse_header(&mut self, level: u32) -> Block {
@@ -103,7 +488,13 @@ impl<'a> Parser<'a> {
         let mut header_toc = Vec::new();
         let mut header_id = String::new();
 
-        while !self.starts_with_newline_next() {
+        // the first header may start at any level
+        if self.level > 0 && level > self.level + 1 {
+            self.warn(format!("heading level skipped from {} to {}", self.level, level));
+        }
+        self.level = level;
+
+        while !self.chs.is_empty() && !self.starts_with_newline_next() {
             header_cont.push(self.parse_primary());
         }
         for prim in &header_cont {
@@ -122,43 +513,133 @@ impl<'a> Parser<'a> {
                 Math { math } => header_id.push_str(math),
                 Code { code } => header_id.push_str(code),
                 Text { text } => header_id.push_str(text),
+                WikiLink { label, .. } => header_id.push_str(label),
+                Kbd { keys } => header_id.push_str(&keys.join("+")),
+                Time { date } => header_id.push_str(date),
                 _ => {},
             }
         }
 
-        // modify title or table of contents
-        if level == 1 {
-            self.title = header_id.clone();
+        // the title keeps the header text, the id goes through the slug function if there is one
+        let header_text = header_id.clone();
+        if let Some(slug) = &self.options.slug {
+            header_id = slug(&header_id);
+        }
+        // prefixed before de-duplication, so documents with different prefixes never collide
+        if let Some(prefix) = &self.options.id_prefix {
+            header_id.insert_str(0, prefix);
+        }
+
+        // a fragment only needs unique ids
+        if self.fragment {
+            let id = self.unique_id(header_id);
+            return Header { prims: header_cont, level, id };
+        }
+
+        // a section parsed in parallel leaves its headers to be registered when it is joined
+        if self.deferred {
+            self.deferred_headers.push((header_toc, header_text));
+            return Header { prims: header_cont, level, id: header_id };
+        }
+
+        let id = self.register_header(header_toc, header_text, header_id, level);
+        Header { prims: header_cont, level, id }
+    }
+
+    // the first level-1 header is the title, every other header goes into the table of contents
+    fn register_header(&mut self, header_toc: Vec<Prim>, header_text: String, header_id: String, level: u32) -> String {
+        let header_id = self.unique_id(header_id);
+        if level == 1 && !self.titled && self.options.treat_h1_as_title {
+            self.title = header_text;
+            self.titled = true;
         } else {
-            let count = self.headers.insert(header_id.clone());
-            if count > 0 {
-                header_id = format!("{}-{}", &header_id, count);
+            // below a level-1 header in the toc, headers nest one level deeper
+            if level == 1 {
+                self.toc_h1 = true;
             }
+            let top = if self.toc_h1 { 1 } else { 2 };
 
+            // attach to the deepest existing level if some levels are skipped
             let mut cur = &mut self.toc;
-            for _ in 2..level {
+            for _ in top..level {
+                if cur.items.is_empty() {
+                    break;
+                }
                 cur = &mut cur.items.last_mut().unwrap().list;
             }
             cur.items.push(ListItem {
                 spans: vec![ PrimElem(Link { text: header_toc, url: format!("#{}", &header_id) }) ],
-                list: List { ordered: true, items: Vec::new() },
+                list: List { ordered: self.options.toc_ordered, loose: false, items: Vec::new() },
+                number: None,
+                marker: None,
             });
         }
-        Header { prims: header_cont, level, id: header_id }
+        header_id
+    }
+
+    fn unique_id(&mut self, id: String) -> String {
+        let count = self.headers.insert(id.clone());
+        if count > 0 {
+            format!("{}-{}", id, count)
+        } else {
+            id
+        }
     }
 
     fn parse_blockquote(&mut self) -> Block {
         let mut lines = Vec::new();
+        let mut last = self.chs;
         while self.starts_with_next("> ") {
+            last = self.chs;
             lines.push(self.parse_spans());
         }
-        Blockquote { lines }
+
+        // a last line starting with a dash names the source of the quote
+        let mut cite = None;
+        if let Some(source) = last.strip_prefix("— ").or_else(|| last.strip_prefix("-- ")) {
+            if lines.len() > 1 {
+                lines.pop();
+                let rest = self.chs;
+                self.chs = source;
+                cite = Some(self.parse_spans());
+                self.chs = rest;
+            }
+        }
+        Blockquote { lines, cite }
     }
 
+    // items nested too deeply are kept at the deepest allowed level
     fn parse_list(&mut self, min_indent: usize) -> List {
+        if !self.enter_nesting() {
+            return List { ordered: false, loose: false, items: Vec::new() };
+        }
+        let list = self.parse_list_items(min_indent);
+        self.depth -= 1;
+        list
+    }
+
+    fn parse_list_items(&mut self, min_indent: usize) -> List {
         let mut ordered = false;
+        let mut loose = false;
         let mut items = Vec::new();
         while !self.chs.is_empty() {
+            // blank lines between items make the list loose
+            if !items.is_empty() {
+                let chs = self.chs;
+                let mut blank = false;
+                while !self.chs.is_empty() && self.skip_blank_line() {
+                    blank = true;
+                }
+                if blank {
+                    if self.list_item_indent().is_some_and(|indent| min_indent <= indent) {
+                        loose = true;
+                    } else {
+                        self.chs = chs;
+                        break;
+                    }
+                }
+            }
+
             let mut indent = 0;
             let mut chs = self.chs;
             while let Some(rest) = chs.strip_prefix(" ") {
@@ -174,6 +655,8 @@ impl<'a> Parser<'a> {
                     items.push(ListItem {
                         spans: self.parse_spans(),
                         list: self.parse_list(indent + 1),
+                        number: None,
+                        marker: Some('-'),
                     });
                     continue;
                 }
@@ -183,37 +666,169 @@ impl<'a> Parser<'a> {
                     items.push(ListItem {
                         spans: self.parse_spans(),
                         list: self.parse_list(indent + 1),
+                        number: None,
+                        marker: Some('+'),
+                    });
+                    continue;
+                }
+
+                if let Some((number, len)) = self.list_number() {
+                    self.chs = &self.chs[len..];
+                    ordered = true;
+                    items.push(ListItem {
+                        spans: self.parse_spans(),
+                        list: self.parse_list(indent + 1),
+                        number: Some(number),
+                        marker: None,
                     });
                     continue;
                 }
             }
             break;
         }
-        List { ordered, items }
+        List { ordered, loose, items }
+    }
+
+    // the indent of a list item starting at the current line
+    fn list_item_indent(&self) -> Option<usize> {
+        let chs = self.chs.trim_start_matches(' ');
+        let digits = chs.len() - chs.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let marker = chs.starts_with("- ") || chs.starts_with("+ ") || ((1..=9).contains(&digits) && chs[digits..].starts_with(". "));
+        marker.then_some(self.chs.len() - chs.len())
     }
 
     fn parse_embed(&mut self) -> Block {
-        let mut text = Vec::new();
+        // parse the caption on its own by cutting the input at the bracket closing `@[`, so that
+        // a link inside the caption stays whole
+        let end = self.matching_bracket(1).unwrap();
+        let rest = &self.chs[end + 2..];
+        self.chs = &self.chs[2..end];
+        let mut text = self.parse_spans();
+        self.chs = rest;
+        escape_ampersands(&mut text);
+
         let mut url = String::new();
-        while !self.starts_with_next("](") {
-            text.push(self.parse_primary());
-        }
+        let offset = self.offset();
         while let Some(c) = self.next_char_until(")") {
             url.push(c);
         }
 
         if url.ends_with(".png") || url.ends_with(".jpg") {
             let title = text;
-            Image { title, url }
+            let attrs = self.parse_image_attrs();
+            Image { title, url, attrs }
         } else {
-            let (title, image, description, site_name) = get_ogp_info(&url);
+            // supplied metadata and offline rendering never fetch
+            let name = url.rsplit('/').next().unwrap_or("");
+            if !name.contains('.') && !self.options.offline && !self.options.metadata.contains_key(&url) {
+                self.warnings.push(Warning { offset, message: format!("embed URL `{}` has no extension and triggered a network fetch", url) });
+            }
+            let OgpInfo { mut title, image, description, site_name } = self.get_ogp_info(&url);
+            // a page without a title falls back to the caption the author wrote
+            if title.is_empty() {
+                title = gen_text(&text);
+            }
             LinkCard { title, image, url, description, site_name }
         }
     }
 
-    fn parse_math_block(&mut self) -> Block {
+    // unknown keys and malformed values are ignored
+    fn parse_image_attrs(&mut self) -> ImageAttrs {
+        let mut attrs = ImageAttrs::default();
+        let line = self.chs.lines().next().unwrap_or("");
+        let Some(end) = line.find('}').filter(|_| line.starts_with('{')) else {
+            return attrs;
+        };
+        for pair in line[1..end].split_whitespace() {
+            let Some((key, value)) = pair.split_once('=') else {
+                continue;
+            };
+            let value = value.trim_matches('"');
+            match key {
+                "width" => attrs.width = css_length(value),
+                "height" => attrs.height = css_length(value),
+                "align" if ["left", "center", "right"].contains(&value) => attrs.align = Some(value.to_string()),
+                _ => {},
+            }
+        }
+        self.chs = &self.chs[end + 1..];
+        attrs
+    }
+
+    // the lines between `<tag>` and its matching `</tag>` are parsed as blocks, and an
+    // optional `<summary>` line right after the opening tag is parsed as spans
+    fn parse_container(&mut self, tag: &'static str) -> Option<Block> {
+        let chs = self.chs;
+        let (first, body) = chs.split_once('\n').unwrap_or((chs, ""));
+        let after = open_tag(first, tag)?;
+        let open = first.trim()[..first.trim().len() - after.len()].to_string();
+        let (summary, body) = match (after.trim(), body.split_once('\n')) {
+            ("", Some((line, rest))) if summary_inner(line).is_some() => (summary_inner(line), rest),
+            ("", _) => (None, body),
+            (after, _) => (Some(summary_inner(after)?), body),
+        };
+        let close = self.fence_closer(tag)?;
+
+        self.chs = summary.unwrap_or("");
+        let summary = summary.map(|_| self.parse_spans());
+        self.chs = &body[..close - (chs.len() - body.len())];
+        let content = self.parse_blocks();
+        self.chs = chs[close..].split_once('\n').map_or("", |(_, rest)| rest);
+        Some(Container { tag: tag.to_string(), open, summary, content })
+    }
+
+    // the blocks of a `:::if(name)` ... `:::` block if `name` is one of the active conditions, and
+    // none otherwise. dropped blocks are never parsed, so they fetch nothing, and a nested block
+    // is only kept when every block around it is
+    fn parse_condition(&mut self) -> Option<Vec<(Block, Range<usize>)>> {
+        let first = self.line();
+        let name = condition_name(first)?;
+        let close = self.fence_closer(":::")?;
+        let chs = self.chs;
+        let body = chs[first.len()..close].strip_prefix('\n')?;
+        let mut blocks = Vec::new();
+        if self.options.conditions.iter().any(|condition| condition == name) && self.enter_nesting() {
+            self.chs = body;
+            blocks = self.parse_ranged_blocks();
+            self.depth -= 1;
+        }
+        self.chs = chs[close..].split_once('\n').map_or("", |(_, rest)| rest);
+        Some(blocks)
+    }
+
+    // `---` lines split the columns and `:::` closes the container, skipping nested containers
+    fn parse_columns(&mut self) -> Option<Block> {
+        let close = self.fence_closer(":::")?;
+        let chs = self.chs;
+        let body = &chs[chs.find('\n')? + 1..close];
+        let mut sources = Vec::new();
+        let (mut start, mut pos, mut depth) = (0, 0, 0);
+        for line in body.split_inclusive('\n') {
+            match line.trim_end() {
+                ":::" => depth -= 1,
+                "---" if depth == 0 => {
+                    sources.push(&body[start..pos]);
+                    start = pos + line.len();
+                },
+                trimmed if trimmed.starts_with("::: ") || condition_name(trimmed).is_some() => depth += 1,
+                _ => {},
+            }
+            pos += line.len();
+        }
+        sources.push(&body[start..]);
+
+        let mut columns = Vec::new();
+        for source in sources {
+            self.chs = source;
+            columns.push(self.parse_blocks());
+        }
+        self.chs = chs[close..].split_once('\n').map_or("", |(_, rest)| rest);
+        Some(Columns { columns })
+    }
+
+    fn parse_math_block(&mut self, close: &str) -> Block {
         let mut math = String::new();
-        while let Some(c) = self.next_char_until("$$") {
+        while let Some(c) = self.next_char_until(close) {
             math.push_str(&self.escape(c));
         }
         MathBlock { math }
@@ -224,43 +839,115 @@ impl<'a> Parser<'a> {
         while let Some(c) = self.next_char_until_newline() {
             lang.push(c);
         }
+        let lang = lang.trim().to_string();
         let mut code = String::new();
         while let Some(c) = self.next_char_until("```") {
-            code.push_str(&self.escape(c));
+            code.push(c);
+        }
+
+        // the newline before the closing fence is not part of the code
+        if code.ends_with('\n') {
+            code.pop();
+            if code.ends_with('\r') {
+                code.pop();
+            }
+        }
+        match lang.as_str() {
+            "csv" => self.csv_table(&code, ','),
+            "tsv" => self.csv_table(&code, '\t'),
+            _ => CodeBlock { lang, code: code.chars().map(|c| self.escape(c)).collect() },
+        }
+    }
+
+    // the first record is the header
+    fn csv_table(&mut self, text: &str, delimiter: char) -> Block {
+        let mut rows = parse_csv(text, delimiter).into_iter().map(|record| {
+            record.iter().map(|field| match field.is_empty() {
+                true => Vec::new(),
+                false => vec![ PrimElem(Text { text: field.chars().map(|c| self.escape(c)).collect() }) ],
+            }).collect()
+        });
+        let head: Vec<_> = rows.next().into_iter().collect();
+        let mut body: Vec<_> = rows.collect();
+        self.fit_rows(&head, &mut body);
+        Table { caption: None, head, body }
+    }
+
+    fn parse_indented_code_block(&mut self) -> Block {
+        let mut lines = Vec::new();
+        while !self.chs.is_empty() {
+            let end = self.chs.find('\n').map_or(self.chs.len(), |i| i + 1);
+            let line = &self.chs[..end];
+            if let Some(rest) = line.strip_prefix("    ").or_else(|| line.strip_prefix('\t')) {
+                lines.push(rest.trim_end_matches(['\n', '\r']));
+            } else if line.trim().is_empty() {
+                lines.push("");
+            } else {
+                break;
+            }
+            self.chs = &self.chs[end..];
+        }
+
+        // trailing blank lines separate the block and are not part of the code
+        while lines.last() == Some(&"") {
+            lines.pop();
         }
-        CodeBlock { lang, code }
+        let code = lines.join("\n").chars().map(|c| self.escape(c)).collect();
+        CodeBlock { lang: String::new(), code }
     }
 
-    fn parse_table(&mut self) -> Block {
+    fn parse_table(&mut self, mut caption: Option<Vec<Span>>) -> Block {
         let mut head = Vec::new();
         let mut body = Vec::new();
         while let Some(row) = self.parse_table_row() {
             head.push(row);
         }
+
         while let Some(row) = self.parse_table_row() {
             body.push(row);
         }
-        Table { head, body }
+        self.fit_rows(&head, &mut body);
+        if caption.is_none() && self.starts_with_next("Table: ") {
+            caption = Some(self.parse_spans());
+        }
+        Table { caption, head, body }
+    }
+
+    // body rows are fitted to the header's column count, padding short rows and truncating long
+    // ones unless that is turned off
+    fn fit_rows(&mut self, head: &[Vec<Vec<Span>>], body: &mut [Vec<Vec<Span>>]) {
+        let columns = head.first().map_or(0, Vec::len);
+        for (i, row) in body.iter_mut().enumerate() {
+            if columns > 0 && row.len() != columns {
+                self.warn(format!("table body row {} has {} cells but the header has {}", i + 1, row.len(), columns));
+                if row.len() < columns || self.options.truncate_table_rows {
+                    row.resize_with(columns, Vec::new);
+                }
+            }
+        }
     }
 
-    fn parse_table_row(&mut self) -> Option<Vec<String>> {
+    fn parse_table_row(&mut self) -> Option<Vec<Vec<Span>>> {
         if !self.starts_with_next("|") {
             return None;
         }
 
-        let mut row: Vec<String> = Vec::new();
-        while !self.chs.is_empty() && !self.starts_with_newline_next() {
-            let mut data = String::new();
-            loop {
-                match self.next_char() {
-                    Some('|') => break,
-                    Some(c)   => data.push_str(&self.escape(c)),
-                    None      => break,
-                }
-            }
-            row.push(data.trim_start().trim_end().to_string());
+        // parse each cell on its own by cutting the line at every `|`, where a closing `|` is optional
+        let line = self.line();
+        let rest = &self.chs[line.len()..];
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let cells: Vec<&str> = if line.is_empty() { Vec::new() } else { line.strip_suffix('|').unwrap_or(line).split('|').collect() };
+        let mut row = Vec::new();
+        let mut delimiter = true;
+        for cell in cells {
+            let cell = cell.trim();
+            delimiter &= cell.chars().all(|c| c == '-');
+            self.chs = cell;
+            row.push(self.parse_spans());
         }
-        if row.iter().all(|s| s.chars().all(|c| c == '-')) {
+        self.chs = rest;
+        self.starts_with_newline_next();
+        if delimiter {
             return None;
         }
         Some(row)
@@ -273,57 +960,164 @@ impl<'a> Parser<'a> {
     fn parse_spans(&mut self) -> Vec<Span> {
         let mut spans = Vec::new();
         while !self.chs.is_empty() && !self.starts_with_newline_next() {
-            // bold
-            if self.chs.starts_with("**") {
-                spans.push(self.parse_bold());
-                continue;
-            }
+            self.push_span(&mut spans);
+        }
+        spans
+    }
 
-            // italic
-            if self.chs.starts_with("__") {
-                spans.push(self.parse_italic());
-                continue;
-            }
+    fn push_span(&mut self, spans: &mut Vec<Span>) {
+        let span = self.parse_span();
+        spans.push(span);
+        if let Some(end) = self.annotation_end() {
+            self.parse_annotation(spans, end);
+        }
+    }
 
-            // primary
-            spans.push(PrimElem(self.parse_primary()));
+    // a `{>>note<<}` closed on the same line, directly after the content it annotates
+    fn annotation_end(&mut self) -> Option<usize> {
+        if !self.chs.starts_with("{>>") {
+            return None;
         }
-        spans
+        self.find_ahead(3, "<<}", true)
     }
 
-    fn parse_bold(&mut self) -> Span {
-        if self.starts_with_next("**") {
-            let mut text = Vec::new();
-            while !self.starts_with_next("**") {
-                text.push(self.parse_italic());
-            }
+    // the note covers the span before it, or only the last word when that span is plain text
+    fn parse_annotation(&mut self, spans: &mut Vec<Span>, end: usize) {
+        if !self.enter_nesting() {
+            return;
+        }
+        let rest = &self.chs[end + 3..];
+        self.chs = &self.chs[3..end];
+        let note = self.parse_spans();
+        self.chs = rest;
+        self.depth -= 1;
+
+        // spacing before the note is kept after it, so `**a** {>>n<<}` annotates the bold text
+        let blank = spans.iter().rev().take_while(|span| matches!(span, PrimElem(Text { text }) if text.trim().is_empty())).count();
+        let blank = spans.split_off(spans.len() - blank);
+        let text = match spans.pop() {
+            Some(PrimElem(Text { text })) => {
+                let start = text.trim_end().rfind(char::is_whitespace).map_or(0, |i| i + 1);
+                let (before, word) = text.split_at(start);
+                if !before.is_empty() {
+                    spans.push(PrimElem(Text { text: before.to_string() }));
+                }
+                vec![ PrimElem(Text { text: word.to_string() }) ]
+            },
+            Some(span) => vec![ span ],
+            None => Vec::new(),
+        };
+        spans.push(Annotation { text, note });
+        spans.extend(blank);
+    }
+
+    fn parse_span(&mut self) -> Span {
+        // bold
+        if self.chs.starts_with("**") {
+            return self.parse_emphasis("**");
+        }
+
+        // italic
+        if self.chs.starts_with("__") {
+            return self.parse_emphasis("__");
+        }
+        if self.chs.starts_with('_') {
+            return self.parse_emphasis("_");
+        }
+
+        // primary
+        PrimElem(self.parse_primary())
+    }
+
+    fn parse_emphasis(&mut self, delim: &'static str) -> Span {
+        let closed = self.flanked_closer(delim).is_some();
+        if !closed {
+            self.warn(format!("unterminated emphasis `{}` treated as literal", delim));
+        }
+        if !closed || !self.enter_nesting() {
+            self.chs = &self.chs[delim.len()..];
+            return PrimElem(Text { text: delim.to_string() });
+        }
+        self.chs = &self.chs[delim.len()..];
+
+        // inner emphasis may use the other delimiters, and always ends at the end of the line
+        let mut text = Vec::new();
+        while !self.chs.is_empty() && !self.chs.starts_with('\n') && !self.chs.starts_with("\r\n") && !self.at_closer(delim) {
+            self.push_span(&mut text);
+        }
+        self.starts_with_next(delim);
+        self.depth -= 1;
+        if delim == "**" {
             Bold { text }
         } else {
-            PrimElem(self.parse_primary())
+            Ital { text }
         }
     }
 
-    fn parse_italic(&mut self) -> Span {
-        if self.starts_with_next("__") {
-            let mut text = Vec::new();
-            while !self.starts_with_next("__") {
-                text.push(self.parse_bold());
-            }
-            Ital { text }
-        } else {
-            PrimElem(self.parse_primary())
+    // the byte index of the `delim` closing the one at the start of the input. an opener must be
+    // followed by a non-space and closed later on the same line after a non-space. whether a `delim`
+    // can close does not depend on the opener, so like find_ahead a miss is remembered for the line
+    fn flanked_closer(&mut self, delim: &'static str) -> Option<usize> {
+        let line = self.line();
+        let line = &line[delim.len()..];
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.is_empty() || line.starts_with(char::is_whitespace) {
+            return None;
+        }
+        let from = self.offset() + delim.len();
+        let end = self.offset() + self.chs.len();
+        if self.closer_misses.iter().any(|(missed, range, chs_end)| *missed == delim && range.contains(&from) && *chs_end == end) {
+            return None;
+        }
+        let found = line.match_indices(delim).map(|(i, _)| i).find(|&i| {
+            i > 0 && !line[..i].ends_with(char::is_whitespace) && !(delim == "_" && line[i + 1..].starts_with(char::is_alphanumeric))
+        });
+        if found.is_none() {
+            self.closer_misses.retain(|(missed, _, _)| *missed != delim);
+            self.closer_misses.push((delim, from..from + line.len() + 1, end));
         }
+        found.map(|i| delim.len() + i)
+    }
+
+    fn at_closer(&self, delim: &str) -> bool {
+        self.chs.starts_with(delim) && !(delim == "_" && self.chs.starts_with("__"))
     }
 
     fn parse_primary(&mut self) -> Prim {
+        // wiki link
+        if self.chs.starts_with("[[") && self.find_ahead(2, "]]", true).is_some() {
+            return self.parse_wiki_link();
+        }
+
+        // inline footnote
+        if self.chs.starts_with("^[") {
+            if let Some(end) = self.matching_bracket(1).filter(|_| self.enter_nesting()) {
+                let note = self.parse_inline_footnote(end);
+                self.depth -= 1;
+                return note;
+            }
+        }
+
         // link
-        if self.starts_with_next("[") {
+        if self.chs.starts_with('[') && self.find_ahead(1, "](", true).is_some() {
+            self.chs = &self.chs[1..];
             self.parse_link()
         } else {
             self.parse_subprimary()
         }
     }
 
+    fn parse_inline_footnote(&mut self, end: usize) -> Prim {
+        // parse the note on its own by cutting the input at the closing bracket
+        let rest = &self.chs[end + 1..];
+        self.chs = &self.chs[2..end];
+        let note = self.parse_spans();
+        self.chs = rest;
+
+        self.footnotes.push(note);
+        FootnoteRef { id: self.footnotes.len() }
+    }
+
     fn parse_link(&mut self) -> Prim {
         let mut text = Vec::new();
         let mut url = String::new();
@@ -341,48 +1135,197 @@ impl<'a> Parser<'a> {
         }
 
         if text.is_empty() {
-            text = vec![ Text { text: get_title(&url) } ];
+            text = vec![ Text { text: self.get_title(&url) } ];
         }
 
         Link { text, url }
     }
 
+    fn parse_autolink(&mut self, end: usize) -> Prim {
+        let target = &self.chs[1..end];
+        self.chs = &self.chs[end + 1..];
+        let url = if target.contains(':') { target.to_string() } else { format!("mailto:{}", target) };
+        let text = target.chars().map(|c| self.escape(c)).collect();
+        Link { text: vec![ Text { text } ], url }
+    }
+
+    fn parse_wiki_link(&mut self) -> Prim {
+        self.chs = &self.chs[2..];
+        let mut inner = String::new();
+        while let Some(c) = self.next_char_until("]]") {
+            inner.push_str(&self.escape(c));
+        }
+        let (target, label) = match inner.split_once('|') {
+            Some((target, label)) => (target.trim().to_string(), label.trim().to_string()),
+            None => (inner.trim().to_string(), inner.trim().to_string()),
+        };
+        WikiLink { target, label }
+    }
+
     fn parse_subprimary(&mut self) -> Prim {
+        // comment
+        if self.chs.starts_with("<!--") {
+            if self.skip_comment() {
+                return Text { text: String::new() };
+            }
+            self.chs = &self.chs[4..];
+            return Text { text: String::from("&lt;!--") };
+        }
+
+        // autolink
+        if let Some(end) = self.autolink_end() {
+            return self.parse_autolink(end);
+        }
+
         // math
-        if self.starts_with_next("$") {
+        if self.chs.starts_with('$') {
             return self.parse_math();
         }
+        if let Some(end) = self.paren_math_end() {
+            let math = self.chs[2..end].chars().map(|c| self.escape(c)).collect();
+            self.chs = &self.chs[end + 2..];
+            return Math { math };
+        }
 
         // code
-        if self.starts_with_next("`") {
+        if self.chs.starts_with('`') {
             return self.parse_code();
         }
 
+        // keyboard shortcut
+        if self.starts_with_next("++") {
+            return self.parse_kbd();
+        }
+
+        // date
+        if self.date_ahead() {
+            let date = self.chs[..10].to_string();
+            self.chs = &self.chs[10..];
+            return Time { date };
+        }
+
         // text
         self.parse_text()
     }
 
     fn parse_math(&mut self) -> Prim {
-        let mut math = String::new();
-        while let Some(c) = self.next_char_until("$") {
-            math.push_str(&self.escape(c));
-        }
-        Math { math }
-    }
+        // `$` opens only before a non-space and closes only after one with strict delimiters
+        let end = if self.options.strict_math_delimiters {
+            self.flanked_closer("$")
+        } else {
+            self.find_ahead(1, "$", true)
+        };
 
+        // a `$` without a closing one on the same line is just a dollar sign
+        let Some(end) = end else {
+            self.chs = &self.chs[1..];
+            return Text { text: String::from("$") };
+        };
+
+        let math = self.chs[1..end].chars().map(|c| self.escape(c)).collect();
+        self.chs = &self.chs[end + 1..];
+        Math { math }
+    }
+
+    // the start of the next run of exactly `ticks` backticks on the line. like find_ahead, a miss
+    // is remembered for the rest of the line
+    fn code_closer(&mut self, ticks: usize) -> Option<usize> {
+        let from = self.offset() + ticks;
+        let end = self.offset() + self.chs.len();
+        if self.code_misses.iter().any(|(missed, range, chs_end)| *missed == ticks && range.contains(&from) && *chs_end == end) {
+            return None;
+        }
+        let line = &self.line()[ticks..];
+        let mut i = 0;
+        while let Some(start) = line[i..].find('`').map(|start| i + start) {
+            let run = line[start..].len() - line[start..].trim_start_matches('`').len();
+            if run == ticks {
+                return Some(ticks + start);
+            }
+            i = start + run;
+        }
+        self.code_misses.retain(|(missed, _, _)| *missed != ticks);
+        self.code_misses.push((ticks, from..from + line.len() + 1, end));
+        None
+    }
+
+    // there is no backslash escaping, so `\(` is math whenever a `\)` closes it on the same line,
+    // and a literal `\(` otherwise
+    fn paren_math_end(&mut self) -> Option<usize> {
+        if !self.chs.starts_with("\\(") {
+            return None;
+        }
+        self.find_ahead(2, "\\)", true)
+    }
+
+    // a run of backticks opens a span that the next run of the same length on its line closes, so
+    // longer runs can hold shorter ones. a run with no closer is literal text
     fn parse_code(&mut self) -> Prim {
-        let mut code = String::new();
-        while let Some(c) = self.next_char_until("`") {
-            code.push_str(&self.escape(c));
+        let ticks = self.chs.len() - self.chs.trim_start_matches('`').len();
+        let Some(end) = self.code_closer(ticks) else {
+            self.chs = &self.chs[ticks..];
+            return Text { text: "`".repeat(ticks) };
+        };
+        let mut code: String = self.chs[ticks..end].chars().map(|c| self.escape(c)).collect();
+        self.chs = &self.chs[end + ticks..];
+        if self.options.normalize_code_spans {
+            code = normalize_code_span(&code);
         }
         Code { code }
     }
 
+    fn parse_kbd(&mut self) -> Prim {
+        // the closer is the last two of a run of `+`, so `++Ctrl++++` is Ctrl and +
+        let line = self.line();
+        // keys are flanked by non-space so that `C++ and C++` stays text
+        let Some(mut end) = line.match_indices("++").map(|(i, _)| i).find(|&i| i > 0) else {
+            return Text { text: String::from("++") };
+        };
+        while line[end + 2..].starts_with('+') {
+            end += 1;
+        }
+        if line.starts_with(char::is_whitespace) || line[..end].ends_with(char::is_whitespace) {
+            return Text { text: String::from("++") };
+        }
+
+        let mut keys = Vec::new();
+        let mut key = String::new();
+        for c in line[..end].chars() {
+            if c == '+' && !key.is_empty() {
+                keys.push(std::mem::take(&mut key));
+            } else {
+                key.push_str(&self.escape(c));
+            }
+        }
+        if !key.is_empty() {
+            keys.push(key);
+        }
+        self.chs = &self.chs[end + 2..];
+        Kbd { keys }
+    }
+
     fn parse_text(&mut self) -> Prim {
         let mut text = String::new();
+        let time_elements = self.options.time_elements;
+        let special = |b: u8| b"_*+^[]$`<>{\\\r\n".contains(&b) || (time_elements && b.is_ascii_digit());
         loop {
-            if ["**", "__", "[", "]", "$", "`", "\n", "\r\n"].iter().any(|prefix| self.chs.starts_with(prefix)) {
-                break Text { text }
+            // characters that can neither start a delimiter nor need escaping are taken in one step
+            let run = self.chs.bytes().position(special).unwrap_or(self.chs.len());
+            if run > 0 {
+                text.push_str(&self.chs[..run]);
+                self.chs = &self.chs[run..];
+                continue;
+            }
+
+            // a single `_` inside a word, as in snake_case, is not a delimiter
+            let underscore = self.chs.starts_with('_')
+                && (!text.ends_with(char::is_alphanumeric) || !self.chs[1..].starts_with(char::is_alphanumeric));
+            let date = !text.ends_with(|c: char| c.is_alphanumeric() || c == '.' || c == '-') && self.date_ahead();
+            if underscore || date || self.autolink_end().is_some() || self.annotation_end().is_some() || self.paren_math_end().is_some() || ["**", "__", "++", "^[", "[", "]", "$", "`", "<!--", "\n", "\r\n"].iter().any(|prefix| self.chs.starts_with(prefix)) {
+                // a delimiter that nothing else took is plain text, so always make progress
+                if !text.is_empty() || self.chs.starts_with('\n') || self.chs.starts_with("\r\n") {
+                    break Text { text }
+                }
             }
             if let Some(c) = self.next_char_until_newline() {
                 text.push_str(&self.escape(c));
@@ -392,14 +1335,28 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn next_char(&mut self) -> Option<char> {
-        let mut chs = self.chs.chars();
-        if let Some(c) = chs.next() {
-            self.chs = chs.as_str();
-            Some(c)
-        } else {
-            None
+    // lists, emphasis, footnotes and containers past the maximum depth are left unparsed
+    // so that deeply nested input cannot overflow the stack
+    fn enter_nesting(&mut self) -> bool {
+        if self.depth >= self.options.max_depth {
+            if !self.too_deep {
+                self.warn(format!("nesting deeper than {} levels treated as literal", self.options.max_depth));
+                self.too_deep = true;
+            }
+            return false;
         }
+        self.depth += 1;
+        true
+    }
+
+    // byte offset of the current position in the source
+    fn offset(&self) -> usize {
+        self.chs.as_ptr() as usize - self.doc.as_ptr() as usize
+    }
+
+    fn warn(&mut self, message: String) {
+        let offset = self.offset();
+        self.warnings.push(Warning { offset, message });
     }
 
     fn next_char_until(&mut self, until: &str) -> Option<char> {
@@ -433,6 +1390,177 @@ impl<'a> Parser<'a> {
         None
     }
 
+    // an ISO date such as 2024-01-02 that is not part of a longer token like a version number
+    fn date_ahead(&self) -> bool {
+        if !self.options.time_elements || self.chs.len() < 10 || !self.chs.is_char_boundary(10) {
+            return false;
+        }
+        let (date, rest) = self.chs.split_at(10);
+        let shape = date.bytes().enumerate().all(|(i, b)| if i == 4 || i == 7 { b == b'-' } else { b.is_ascii_digit() });
+        let mut rest = rest.chars();
+        let next = rest.next();
+        let joined = next.is_some_and(|c| c.is_alphanumeric() || c == '_')
+            || (matches!(next, Some('.' | '-')) && rest.next().is_some_and(|c| c.is_ascii_digit()));
+        shape && !joined && NaiveDate::parse_from_str(date, "%Y-%m-%d").is_ok()
+    }
+
+    // the byte index of the `>` closing a `<scheme:...>` or `<user@domain>` autolink
+    fn autolink_end(&mut self) -> Option<usize> {
+        if !self.chs.starts_with('<') {
+            return None;
+        }
+        let end = self.find_ahead(1, ">", true)?;
+        let target = &self.chs[1..end];
+        if target.is_empty() || target.contains(|c: char| c.is_whitespace() || c == '<') {
+            return None;
+        }
+
+        // a scheme is a letter followed by letters, digits, `+`, `-` or `.`
+        if let Some((scheme, _)) = target.split_once(':') {
+            let mut chars = scheme.chars();
+            if chars.next().is_some_and(|c| c.is_ascii_alphabetic()) && chars.all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c)) {
+                return Some(end);
+            }
+            return None;
+        }
+        let (user, domain) = target.split_once('@')?;
+        if !user.is_empty() && domain.contains('.') && !domain.starts_with('.') && !domain.ends_with('.') && !domain.contains('@') {
+            return Some(end);
+        }
+        None
+    }
+
+    // the rest of the current line, without the newline. its end is remembered, so that probing for
+    // closers at many positions of one long line does not search for the end every time
+    fn line(&mut self) -> &'a str {
+        let start = self.offset();
+        let end = start + self.chs.len();
+        if !self.line.as_ref().is_some_and(|(line, chs_end)| line.contains(&start) && *chs_end == end) {
+            let len = self.chs.find('\n').unwrap_or(self.chs.len());
+            self.line = Some((start..start + len + 1, end));
+        }
+        let newline = self.line.as_ref().map_or(start, |(line, _)| line.end - 1);
+        let chs = self.chs;
+        &chs[..newline - start]
+    }
+
+    // the byte index of the `]` matching the `[` at `open`, on the same line. all brackets up to the
+    // end of the line are paired in one pass, so a run of openers does not rescan the line
+    fn matching_bracket(&mut self, open: usize) -> Option<usize> {
+        let start = self.offset();
+        let from = start + open;
+        let end = start + self.chs.len();
+        if !self.brackets.as_ref().is_some_and(|(line, chs_end, _)| line.contains(&from) && *chs_end == end) {
+            let line = self.chs[open..].split('\n').next().unwrap_or("");
+            let mut opens = Vec::new();
+            let mut pairs = HashMap::new();
+            for (i, c) in line.char_indices() {
+                match c {
+                    '[' => opens.push(from + i),
+                    ']' => if let Some(open) = opens.pop() {
+                        pairs.insert(open, from + i);
+                    },
+                    _ => {},
+                }
+            }
+            self.brackets = Some((from..from + line.len(), end, pairs));
+        }
+        self.brackets.as_ref().and_then(|(_, _, pairs)| pairs.get(&from)).map(|close| close - start)
+    }
+
+    // the byte index of the next `pat` after the first `skip` bytes, up to the end of the line if
+    // `line` is set. a miss stays a miss for every later position, so it is remembered to keep
+    // runs of unmatched delimiters from rescanning the rest of the input
+    fn find_ahead(&mut self, skip: usize, pat: &'static str, line: bool) -> Option<usize> {
+        let from = self.offset() + skip;
+        let end = self.offset() + self.chs.len();
+        let missed = |(missed, range, chs_end): &(&str, Range<usize>, usize)| *missed == pat && range.contains(&from) && *chs_end == end;
+        if self.misses.iter().any(missed) {
+            return None;
+        }
+        let rest = if line { &self.line()[skip..] } else { &self.chs[skip..] };
+        let found = rest.find(pat).map(|i| skip + i);
+        if found.is_none() {
+            self.misses.retain(|(missed, _, _)| *missed != pat);
+            self.misses.push((pat, from..from + rest.len() + 1, end));
+        }
+        found
+    }
+
+    // the byte index of the line closing the block opened on the current line, which is `:::` for
+    // a `:::` fence and `</tag>` for a container tag. every opener up to the end of the input is
+    // paired in one pass and kept, so a run of unclosed openers does not rescan the rest of the
+    // input for each of them
+    fn fence_closer(&mut self, kind: &'static str) -> Option<usize> {
+        let start = self.offset();
+        let end = start + self.chs.len();
+        // pairings for inputs that end before this point are never needed again
+        self.fences.retain(|(_, scanned, _)| scanned.end > start);
+        let cached = self.fences.iter().position(|(fence, scanned, _)| *fence == kind && scanned.start <= start && scanned.end == end);
+        let i = match cached {
+            Some(i) => i,
+            None => {
+                let close = format!("</{}>", kind);
+                let mut opens = Vec::new();
+                let mut pairs = HashMap::new();
+                let mut pos = start;
+                for line in self.chs.split_inclusive('\n') {
+                    let (opener, closer) = if kind == ":::" {
+                        let trimmed = line.trim_end();
+                        (trimmed.starts_with("::: ") || condition_name(trimmed).is_some(), trimmed == ":::")
+                    } else {
+                        (open_tag(line, kind).is_some(), line.trim() == close)
+                    };
+                    if opener {
+                        opens.push(pos);
+                    } else if closer {
+                        if let Some(open) = opens.pop() {
+                            pairs.insert(open, pos);
+                        }
+                    }
+                    pos += line.len();
+                }
+                self.fences.retain(|(fence, scanned, _)| *fence != kind || scanned.end != end);
+                self.fences.push((kind, start..end, pairs));
+                self.fences.len() - 1
+            },
+        };
+        self.fences[i].2.get(&start).map(|close| close - start)
+    }
+
+    // the number and marker length of a `12. ` list marker
+    fn list_number(&self) -> Option<(u32, usize)> {
+        let digits = self.chs.len() - self.chs.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        if digits == 0 || digits > 9 || !self.chs[digits..].starts_with(". ") {
+            return None;
+        }
+        Some((self.chs[..digits].parse().unwrap(), digits + 2))
+    }
+
+    // an unterminated comment is left in place to be read as text
+    fn skip_comment(&mut self) -> bool {
+        if !self.chs.starts_with("<!--") {
+            return false;
+        }
+        match self.find_ahead(4, "-->", false) {
+            Some(i) => {
+                self.chs = &self.chs[i + 3..];
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn skip_blank_line(&mut self) -> bool {
+        let line = self.line();
+        if line.trim().is_empty() {
+            self.chs = &self.chs[(line.len() + 1).min(self.chs.len())..];
+            true
+        } else {
+            false
+        }
+    }
+
     fn starts_with_next(&mut self, prefix: &str) -> bool {
         if let Some(chs) = self.chs.strip_prefix(prefix) {
             self.chs = chs;
@@ -454,6 +1582,26 @@ impl<'a> Parser<'a> {
         }
     }
 
+    // supplied metadata is used before anything fetched
+    fn get_title(&mut self, url: &str) -> String {
+        if let Some(info) = self.options.metadata.get(url) {
+            return info.title.clone();
+        }
+        if let Some(title) = self.fetched.titles.get(url) {
+            return title.clone();
+        }
+        self.pending.push(Fetch::Title(url.to_string()));
+        String::new()
+    }
+
+    fn get_ogp_info(&mut self, url: &str) -> OgpInfo {
+        if let Some(info) = self.options.metadata.get(url).or_else(|| self.fetched.ogp.get(url)) {
+            return info.clone();
+        }
+        self.pending.push(Fetch::Ogp(url.to_string()));
+        OgpInfo::default()
+    }
+
     fn escape(&self, c: char) -> String {
         match c {
             '<' => String::from("&lt;"),
@@ -463,54 +1611,789 @@ impl<'a> Parser<'a> {
     }
 }
 
-#[tokio::main]
-async fn get_title(url: &String) -> String {
-    let client = reqwest::Client::new();
-    let Ok(res) = client.get(url).header(header::ACCEPT, header::HeaderValue::from_str("text/html").unwrap()).send().await else {
-        return String::new();
-    };
-    let Ok(body) = res.text().await else {
-        return String::new();
-    };
-    let regex = Regex::new("<title>(.*)</title>").unwrap();
-    if let Some(caps) = regex.captures(&body) {
-        return caps[1].to_string().clone();
+// quoted fields may contain the delimiter, newlines and doubled quotes
+fn parse_csv(text: &str, delimiter: char) -> Vec<Vec<String>> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            },
+            '"' if quoted => quoted = false,
+            '"' if field.is_empty() => quoted = true,
+            c if quoted => field.push(c),
+            c if c == delimiter => record.push(std::mem::take(&mut field)),
+            '\r' => {},
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            },
+            c => field.push(c),
+        }
     }
-    return String::new();
+    record.push(field);
+    records.push(record);
+
+    // blank lines are not records
+    records.retain(|record| record.len() > 1 || !record[0].is_empty());
+    records
 }
 
-#[tokio::main]
-async fn get_ogp_info(url: &String) -> (String, Option<String>, Option<String>, Option<String>) {
-    let mut title = String::new();
-    let mut image = None;
-    let mut description = None;
-    let mut site_name = None;
+fn is_blank(span: &Span) -> bool {
+    matches!(span, PrimElem(Text { text }) if text.trim().is_empty())
+}
 
-    let client = reqwest::Client::new();
-    let Ok(res) = client.get(url).header(header::ACCEPT, header::HeaderValue::from_str("text/html").unwrap()).send().await else {
-        return (title, image, description, site_name);
-    };
-    let Ok(body) = res.text().await else {
-        return (title, image, description, site_name);
-    };
+fn push_images(content: &mut Vec<(Block, Range<usize>)>, run: &mut Vec<(Block, Range<usize>)>, threshold: usize) {
+    if run.len() >= threshold {
+        let range = run[0].1.start..run[run.len() - 1].1.end;
+        let images = run.drain(..).map(|(image, _)| image).collect();
+        content.push((Gallery { images }, range));
+    } else {
+        content.append(run);
+    }
+}
+
+const CONTAINER_TAGS: [&str; 4] = ["details", "aside", "section", "div"];
+
+// the rest of the line after a bare `<tag>`, or `<details open>`
+fn open_tag<'a>(line: &'a str, tag: &str) -> Option<&'a str> {
+    let rest = line.trim().strip_prefix('<')?.strip_prefix(tag)?;
+    let rest = if tag == "details" { rest.strip_prefix(" open").unwrap_or(rest) } else { rest };
+    rest.strip_prefix('>')
+}
+
+// as in CommonMark, line endings become spaces and one space is stripped from both ends when
+// both ends have one, while a span of only spaces is kept
+fn normalize_code_span(code: &str) -> String {
+    let code = code.replace("\r\n", " ").replace('\n', " ");
+    if code.len() >= 2 && code.starts_with(' ') && code.ends_with(' ') && !code.bytes().all(|b| b == b' ') {
+        return code[1..code.len() - 1].to_string();
+    }
+    code
+}
 
-    let regex = Regex::new("property=\"og:([^\"]*)\" content=\"([^\"]*)\"").unwrap();
-    for caps in regex.captures_iter(&body) {
-        match &caps[1] {
-            "title" => { title = caps[2].to_string(); },
-            "image" => { image = Some(caps[2].to_string()); },
-            "description" => { description = Some(caps[2].to_string()); },
-            "site_name" => { site_name = Some(caps[2].to_string()); },
+// the name in a `:::if(name)` line
+fn condition_name(line: &str) -> Option<&str> {
+    line.trim_end().strip_prefix(":::")?.trim_start().strip_prefix("if(")?.strip_suffix(')')
+}
+
+fn summary_inner(line: &str) -> Option<&str> {
+    line.trim().strip_prefix("<summary>")?.strip_suffix("</summary>")
+}
+
+// a number with an optional unit, where a bare number is in pixels
+fn css_length(value: &str) -> Option<String> {
+    let number = value.trim_end_matches(|c: char| c.is_ascii_alphabetic() || c == '%');
+    let unit = &value[number.len()..];
+    if number.is_empty() || number.parse::<f64>().is_err() || !["", "px", "%", "em", "rem", "vw", "vh"].contains(&unit) {
+        return None;
+    }
+    Some(format!("{}{}", number, if unit.is_empty() { "px" } else { unit }))
+}
+
+#[cfg(feature = "parallel")]
+const MIN_SECTION_LEN: usize = 16 * 1024;
+
+// split before the headers that start a top-level block, keeping fences, math blocks, comments
+// and containers whole, and pair each section with the level of the header before it
+#[cfg(feature = "parallel")]
+fn split_sections(source: &str) -> Vec<(&str, u32)> {
+    let mut sections = Vec::new();
+    let (mut start, mut pos) = (0, 0);
+    let (mut level, mut start_level) = (0, 0);
+    let mut close: Option<&str> = None;
+    let mut depth = 0;
+    for line in source.split_inclusive('\n') {
+        let hashes = line.len() - line.trim_start_matches('#').len();
+        if let Some(delim) = close {
+            if line.contains(delim) {
+                close = None;
+            }
+        } else if line.starts_with("```") {
+            close = Some("```");
+        } else if line.strip_prefix("$$").is_some_and(|rest| !rest.contains("$$")) {
+            close = Some("$$");
+        } else if line.strip_prefix("\\[").is_some_and(|rest| !rest.contains("\\]")) {
+            close = Some("\\]");
+        } else if line.strip_prefix("<!--").is_some_and(|rest| !rest.contains("-->")) {
+            close = Some("-->");
+        } else if line.trim_end() == "::: columns" || condition_name(line).is_some() || CONTAINER_TAGS.iter().any(|tag| open_tag(line, tag).is_some()) {
+            depth += 1;
+        } else if line.trim_end() == ":::" || CONTAINER_TAGS.iter().any(|tag| line.trim().strip_prefix("</").and_then(|rest| rest.strip_suffix('>')) == Some(tag)) {
+            depth = 0.max(depth - 1);
+        } else if (1..=6).contains(&hashes) && line[hashes..].starts_with([' ', '\t']) {
+            // small sections are not worth a task of their own
+            if depth == 0 && pos - start >= MIN_SECTION_LEN {
+                sections.push((&source[start..pos], start_level));
+                start = pos;
+                start_level = level;
+            }
+            level = hashes as u32;
+        }
+        pos += line.len();
+    }
+    sections.push((&source[start..], start_level));
+    sections
+}
+
+// headers in document order, including those inside containers and columns
+#[cfg(feature = "parallel")]
+fn collect_headers<'b>(blocks: &'b mut [Block], headers: &mut Vec<&'b mut Block>) {
+    for block in blocks {
+        match block {
+            Header { .. } => headers.push(block),
+            Container { content, .. } => collect_headers(content, headers),
+            Columns { columns } => columns.iter_mut().for_each(|column| collect_headers(column, headers)),
             _ => {},
         }
     }
+}
+
+#[cfg(feature = "parallel")]
+fn shift_footnotes(block: &mut Block, base: usize) {
+    match block {
+        Header { prims, .. } => shift_prim_footnotes(prims, base),
+        Image { title, .. } => shift_span_footnotes(title, base),
+        Blockquote { lines, cite } => lines.iter_mut().chain(cite).for_each(|spans| shift_span_footnotes(spans, base)),
+        ListElement(list) => shift_list_footnotes(list, base),
+        Gallery { images } => images.iter_mut().for_each(|image| shift_footnotes(image, base)),
+        Columns { columns } => columns.iter_mut().flatten().for_each(|block| shift_footnotes(block, base)),
+        Container { summary, content, .. } => {
+            summary.iter_mut().for_each(|spans| shift_span_footnotes(spans, base));
+            content.iter_mut().for_each(|block| shift_footnotes(block, base));
+        },
+        Table { caption, head, body } => {
+            caption.iter_mut().for_each(|spans| shift_span_footnotes(spans, base));
+            head.iter_mut().chain(body).flatten().for_each(|spans| shift_span_footnotes(spans, base));
+        },
+        Paragraph { spans } => shift_span_footnotes(spans, base),
+        LinkCard { .. } | MathBlock { .. } | CodeBlock { .. } => {},
+    }
+}
 
-    if title.is_empty() {
-        let regex = Regex::new("<title>(.*)</title>").unwrap();
-        if let Some(caps) = regex.captures(&body) {
-            title = caps[1].to_string();
+#[cfg(feature = "parallel")]
+fn shift_list_footnotes(list: &mut List, base: usize) {
+    for item in &mut list.items {
+        shift_span_footnotes(&mut item.spans, base);
+        shift_list_footnotes(&mut item.list, base);
+    }
+}
+
+#[cfg(feature = "parallel")]
+fn shift_span_footnotes(spans: &mut [Span], base: usize) {
+    for span in spans {
+        match span {
+            Bold { text } | Ital { text } => shift_span_footnotes(text, base),
+            Annotation { text, note } => {
+                shift_span_footnotes(text, base);
+                shift_span_footnotes(note, base);
+            },
+            PrimElem(prim) => shift_prim_footnotes(std::slice::from_mut(prim), base),
         }
     }
+}
+
+#[cfg(feature = "parallel")]
+fn shift_prim_footnotes(prims: &mut [Prim], base: usize) {
+    for prim in prims {
+        match prim {
+            Link { text, .. } => shift_prim_footnotes(text, base),
+            FootnoteRef { id } => *id += base,
+            _ => {},
+        }
+    }
+}
 
-    (title, image, description, site_name)
-}
\ No newline at end of file
+fn merge_block_texts(block: &mut Block) {
+    match block {
+        Header { prims, .. } => merge_prims(prims),
+        Image { title, .. } => merge_spans(title),
+        Blockquote { lines, cite } => {
+            lines.iter_mut().chain(cite).for_each(merge_spans);
+        },
+        ListElement(list) => merge_list_texts(list),
+        Gallery { images } => images.iter_mut().for_each(merge_block_texts),
+        Columns { columns } => columns.iter_mut().flatten().for_each(merge_block_texts),
+        Container { summary, content, .. } => {
+            summary.iter_mut().for_each(merge_spans);
+            content.iter_mut().for_each(merge_block_texts);
+        },
+        Table { caption, head, body } => {
+            caption.iter_mut().for_each(merge_spans);
+            head.iter_mut().chain(body).flatten().for_each(merge_spans);
+        },
+        Paragraph { spans } => merge_spans(spans),
+        LinkCard { .. } | MathBlock { .. } | CodeBlock { .. } => {},
+    }
+}
+
+fn merge_list_texts(list: &mut List) {
+    for item in &mut list.items {
+        merge_spans(&mut item.spans);
+        merge_list_texts(&mut item.list);
+    }
+}
+
+// a bare `&` would start a broken entity in the caption, while `&amp;` and the like are kept
+fn escape_ampersands(spans: &mut Vec<Span>) {
+    for span in spans {
+        match span {
+            Bold { text } | Ital { text } => escape_ampersands(text),
+            Annotation { text, note } => {
+                escape_ampersands(text);
+                escape_ampersands(note);
+            },
+            PrimElem(Text { text }) => *text = escape_bare_ampersands(text),
+            _ => {},
+        }
+    }
+}
+
+// emphasis is a boundary, so only texts directly next to each other in one vector are joined
+fn merge_spans(spans: &mut Vec<Span>) {
+    let mut merged: Vec<Span> = Vec::new();
+    for mut span in spans.drain(..) {
+        match &mut span {
+            Bold { text } | Ital { text } => merge_spans(text),
+            Annotation { text, note } => {
+                merge_spans(text);
+                merge_spans(note);
+            },
+            PrimElem(Link { text, .. }) => merge_prims(text),
+            PrimElem(Text { text }) => {
+                if text.is_empty() {
+                    continue;
+                }
+                if let Some(PrimElem(Text { text: last })) = merged.last_mut() {
+                    last.push_str(text);
+                    continue;
+                }
+            },
+            _ => {},
+        }
+        merged.push(span);
+    }
+    *spans = merged;
+}
+
+fn merge_prims(prims: &mut Vec<Prim>) {
+    let mut merged: Vec<Prim> = Vec::new();
+    for mut prim in prims.drain(..) {
+        match &mut prim {
+            Link { text, .. } => merge_prims(text),
+            Text { text } => {
+                if text.is_empty() {
+                    continue;
+                }
+                if let Some(Text { text: last }) = merged.last_mut() {
+                    last.push_str(text);
+                    continue;
+                }
+            },
+            _ => {},
+        }
+        merged.push(prim);
+    }
+    *prims = merged;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use crate::codegen::{gen_blocks_html, gen_prim_text, gen_toc_html};
+
+    fn options() -> RenderOptions {
+        RenderOptions::default().with_offline(true)
+    }
+
+    fn html(doc: &str, options: &RenderOptions) -> String {
+        gen_blocks_html(&parse_markdown(doc, options).content, 0, options).concat()
+    }
+
+    #[test]
+    fn strict_math_needs_non_space_inside_delimiters() {
+        let strict = options().with_strict_math_delimiters(true);
+        assert!(html("it cost $5 and $6\n", &strict).contains("<p>it cost $5 and $6</p>"));
+        assert!(html("$x+y$ and $ a $\n", &strict).contains("<p>\\(x+y\\) and $ a $</p>"));
+        assert!(html("it cost $5 and $6\n", &options()).contains("\\(5 and \\)"));
+    }
+
+    #[test]
+    fn blank_lines_leave_no_empty_paragraphs() {
+        let doc = parse_markdown("a\n\n \t \n\n\nb\n\n  <!-- c --> \n\n", &options());
+        assert_eq!(doc.content.len(), 2);
+        assert!(!html("a\n\n \t \n\n\nb\n", &options()).contains("<p></p>"));
+    }
+
+    #[test]
+    fn adjacent_blocks_do_not_merge() {
+        let doc = parse_markdown("> quote\n- item\n", &options());
+        assert!(matches!(doc.content[..], [Blockquote { .. }, ListElement(_)]));
+
+        let doc = parse_markdown("- item\nparagraph\n", &options());
+        assert!(matches!(doc.content[..], [ListElement(_), Paragraph { .. }]));
+
+        let doc = parse_markdown("> quote\nparagraph\n", &options());
+        assert!(matches!(doc.content[..], [Blockquote { .. }, Paragraph { .. }]));
+    }
+
+    #[test]
+    fn number_after_paragraph_line_is_text() {
+        let doc = parse_markdown("It was the summer of\n1999. We were young.\n", &options());
+        assert!(matches!(doc.content[..], [Paragraph { .. }, Paragraph { .. }]));
+
+        let doc = parse_markdown("Steps:\n\n1. one\n2. two\n", &options());
+        assert!(matches!(&doc.content[..], [Paragraph { .. }, ListElement(list)] if list.items.len() == 2));
+
+        let doc = parse_markdown("## Steps\n1. one\n", &options());
+        assert!(matches!(doc.content[..], [Header { .. }, ListElement(_)]));
+    }
+
+    #[test]
+    fn ordered_items_keep_explicit_numbers() {
+        let doc = parse_markdown("1. a\n1. b\n5. c\n6. d\n", &options());
+        let ListElement(list) = &doc.content[0] else { panic!("expected a list") };
+        let numbers: Vec<_> = list.items.iter().map(|item| item.number).collect();
+        assert_eq!(numbers, [Some(1), Some(1), Some(5), Some(6)]);
+
+        let html = html("3. a\n4. b\n10. c\n11. d\n", &options());
+        assert!(html.contains("<ol start=\"3\">"));
+        assert_eq!(html.matches("value=").count(), 1);
+        assert!(html.contains("<li value=\"10\">"));
+    }
+
+    #[test]
+    fn code_block_drops_only_the_newline_before_the_fence() {
+        assert!(html("```\ncode\n\n  indented\n```\n", &options()).contains(">code\n\n  indented</code></pre>"));
+        assert!(html("```\nblank after\n\n```\n", &options()).contains(">blank after\n</code></pre>"));
+    }
+
+    #[test]
+    fn nested_and_mixed_emphasis() {
+        let opts = options();
+        assert!(html("**a _b_ c**\n", &opts).contains("<p><strong>a <em>b</em> c</strong></p>"));
+        assert!(html("__**x**__\n", &opts).contains("<p><em><strong>x</strong></em></p>"));
+        assert!(html("**a __b__ c**\n", &opts).contains("<p><strong>a <em>b</em> c</strong></p>"));
+
+        // an unclosed inner delimiter stays literal without breaking the outer one
+        let doc = parse_markdown("**a _b c**\n", &opts);
+        assert!(gen_blocks_html(&doc.content, 0, &opts)[0].contains("<strong>a _b c</strong>"));
+        assert_eq!(doc.warnings.len(), 1);
+
+        assert!(html("**x**, (_y_). \"__z__\"\n", &opts).contains("<strong>x</strong>, (<em>y</em>). \"<em>z</em>\""));
+    }
+
+    #[test]
+    fn math_in_table_cells_and_quotes() {
+        let opts = options();
+        let table = html("| $a^2$ | b |\n|---|---|\n| c | $a^2$ |\n", &opts);
+        assert_eq!(table.matches("<td>\\(a^2\\)</td>").count(), 2);
+        assert!(html("> $a^2$ and $b$\n", &opts).contains("<p>\\(a^2\\) and \\(b\\)</p>"));
+    }
+
+    fn count_spans(spans: &[Span]) -> usize {
+        spans.iter().map(|span| match span {
+            Bold { text } | Ital { text } => 1 + count_spans(text),
+            _ => 1,
+        }).sum()
+    }
+
+    #[test]
+    fn adjacent_texts_merge_within_formatting_only() {
+        let text = |text: &str| PrimElem(Text { text: text.to_string() });
+        let mut spans = vec![text("a"), text("b"), Bold { text: vec![text("c"), text("d")] }, text("e"), text("f")];
+        assert_eq!(count_spans(&spans), 7);
+        merge_spans(&mut spans);
+        assert_eq!(count_spans(&spans), 4);
+        assert!(matches!(&spans[..], [PrimElem(Text { text: ab }), Bold { .. }, PrimElem(Text { text: ef })] if ab == "ab" && ef == "ef"));
+
+        let doc = parse_markdown("a ] b [ c $ d _ e\n", &options());
+        assert!(matches!(&doc.content[..], [Paragraph { spans }] if spans.len() == 1));
+    }
+
+    #[test]
+    fn columns_split_on_separators_and_skip_nested_blocks() {
+        let doc = parse_markdown("::: columns\na\n---\n::: columns\nb\n---\nc\n:::\n:::\nafter\n", &options());
+        let [Columns { columns }, Paragraph { .. }] = &doc.content[..] else { panic!("expected columns then a paragraph") };
+        assert_eq!(columns.len(), 2);
+        assert!(matches!(&columns[1][..], [Columns { columns }] if columns.len() == 2));
+    }
+
+    #[test]
+    fn unclosed_columns_stay_linear() {
+        let doc = "::: columns\n".repeat(20000);
+        let start = std::time::Instant::now();
+        let parsed = parse_markdown(&doc, &options());
+        assert!(start.elapsed().as_secs() < 2);
+        assert_eq!(parsed.content.len(), 20000);
+    }
+
+    #[test]
+    fn lead_skips_paragraphs_in_columns() {
+        let lead = options().with_lead_class("lead");
+        let html = html("::: columns\nleft\n---\nright\n:::\n\nfirst\n\nsecond\n", &lead);
+        assert_eq!(html.matches("class=\"lead\"").count(), 1);
+        assert!(html.contains("<p class=\"lead\">first</p>"));
+    }
+
+    #[test]
+    fn loose_lists_wrap_items_in_paragraphs() {
+        let tight = parse_markdown("- a\n- b\n", &options());
+        assert!(matches!(&tight.content[..], [ListElement(list)] if !list.loose));
+        assert!(!html("- a\n- b\n", &options()).contains("<p>"));
+
+        let loose = parse_markdown("- a\n\n- b\n", &options());
+        assert!(matches!(&loose.content[..], [ListElement(list)] if list.loose && list.items.len() == 2));
+        assert_eq!(html("- a\n\n- b\n", &options()).matches("<p>").count(), 2);
+    }
+
+    #[test]
+    fn only_the_first_top_level_header_is_the_title() {
+        let doc = parse_markdown("# First\n\n# Second\n\n## Sub\n", &options());
+        assert_eq!(doc.title, "First");
+        assert!(matches!(&doc.content[..], [Header { level: 1, .. }, Header { level: 1, id, .. }, Header { level: 2, .. }] if id == "Second"));
+        assert_eq!(doc.toc.items.len(), 1);
+        assert_eq!(doc.toc.items[0].list.items.len(), 1);
+    }
+
+    #[test]
+    fn containers_parse_markdown_inside() {
+        let doc = parse_markdown("<details>\n<summary>More _info_</summary>\n<div>\n- a\n</div>\n\ntext\n</details>\nafter\n", &options());
+        let [Container { tag, summary: Some(_), content, .. }, Paragraph { .. }] = &doc.content[..] else { panic!("expected a container then a paragraph") };
+        assert_eq!(tag, "details");
+        assert!(matches!(&content[..], [Container { content, .. }, Paragraph { .. }] if matches!(content[..], [ListElement(_)])));
+    }
+
+    #[test]
+    fn unclosed_containers_stay_linear() {
+        let doc = "<details>\n<div>\n::: columns\n".repeat(10000);
+        let start = std::time::Instant::now();
+        let parsed = parse_markdown(&doc, &options());
+        assert!(start.elapsed().as_secs() < 2);
+        assert_eq!(parsed.content.len(), 30000);
+    }
+
+    fn body_widths(doc: &Document) -> Vec<usize> {
+        let [Table { body, .. }] = &doc.content[..] else { panic!("expected a table") };
+        body.iter().map(Vec::len).collect()
+    }
+
+    #[test]
+    fn table_rows_fit_the_header() {
+        let source = "| a | b | c |\n|---|---|---|\n| 1 |\n| 1 | 2 | 3 | 4 |\n| 1 | 2 | 3 |\n";
+        let doc = parse_markdown(source, &options());
+        assert_eq!(body_widths(&doc), [3, 3, 3]);
+        assert_eq!(doc.warnings.len(), 2);
+        assert!(doc.warnings[0].message.contains("row 1 has 1 cells"));
+
+        let doc = parse_markdown(source, &options().with_truncate_table_rows(false));
+        assert_eq!(body_widths(&doc), [3, 4, 3]);
+    }
+
+    #[test]
+    fn csv_rows_fit_the_header() {
+        let doc = parse_markdown("```csv\na,b,c\n1\n1,\"2,5\",3,4\n```\n", &options());
+        assert_eq!(body_widths(&doc), [3, 3]);
+        assert_eq!(doc.warnings.len(), 2);
+    }
+
+    #[test]
+    fn leading_bom_keeps_the_title() {
+        let doc = parse_markdown("\u{feff}# Title\n\n## Section\n", &options());
+        assert_eq!(doc.title, "Title");
+        assert!(matches!(&doc.content[..], [Header { level: 1, .. }, Header { level: 2, .. }]));
+        assert_eq!(doc.ranges[0].start, 3);
+    }
+
+    #[test]
+    fn atx_headers_allow_tabs_and_closing_hashes() {
+        let doc = parse_markdown("### Title ###\n\n#\tTabbed\n\n##  Spaced ##  \n\n## C# ##\n", &options());
+        let texts: Vec<_> = doc.content.iter().map(|block| match block {
+            Header { prims, level, .. } => (*level, prims.iter().map(gen_prim_text).collect::<String>()),
+            _ => panic!("expected only headers"),
+        }).collect();
+        assert_eq!(texts, [(3, "Title".to_string()), (1, "Tabbed".to_string()), (2, "Spaced".to_string()), (2, "C#".to_string())]);
+    }
+
+    #[test]
+    fn embed_caption_is_parsed_as_spans() {
+        let html = html("@[**bold** & <x>](a.png)\n", &options());
+        assert!(html.contains("<p class=\"caption\"><strong>bold</strong> &amp; &lt;x&gt;</p>"));
+    }
+
+    #[test]
+    fn embed_caption_keeps_a_nested_link() {
+        let doc = parse_markdown("@[see [docs](https://a.example/x) here](a.png)\n", &options());
+        assert!(matches!(&doc.content[..], [Image { url, .. }] if url == "a.png"));
+        let html = gen_blocks_html(&doc.content, 0, &options()).concat();
+        assert!(html.contains("<p class=\"caption\">see <a href=\"https://a.example/x\">docs</a> here</p>"));
+    }
+
+    #[test]
+    fn heading_skips_warn_only_after_a_first_header() {
+        assert!(parse_markdown("## Start\n\n### Next\n", &options()).warnings.is_empty());
+        let warnings = parse_markdown("## Start\n\n#### Deep\n", &options()).warnings;
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].message.contains("from 2 to 4"));
+    }
+
+    #[test]
+    fn embeds_without_a_fetch_do_not_warn() {
+        assert!(parse_markdown("@[page](https://a.example/page)\n", &options()).warnings.is_empty());
+
+        let metadata = HashMap::from([(String::from("https://a.example/page"), OgpInfo::default())]);
+        let known = RenderOptions::default().with_metadata(metadata);
+        assert!(parse_markdown("@[page](https://a.example/page)\n", &known).warnings.is_empty());
+    }
+
+    fn header_ids(content: &[Block]) -> Vec<String> {
+        content.iter().filter_map(|block| match block {
+            Header { id, .. } => Some(id.clone()),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn shared_header_ids_stay_unique_across_threads() {
+        let ids = Arc::new(Mutex::new(MultiSet::new()));
+        let options = Arc::new(options().with_header_ids(ids));
+        let threads: Vec<_> = (0..8).map(|_| {
+            let options = Arc::clone(&options);
+            std::thread::spawn(move || header_ids(&parse_markdown("## Intro\n\n## Usage\n", &options).content))
+        }).collect();
+        let mut all: Vec<String> = threads.into_iter().flat_map(|thread| thread.join().unwrap()).collect();
+        all.sort();
+        all.dedup();
+        assert_eq!(all.len(), 16);
+    }
+
+    #[test]
+    fn fragments_do_not_take_shared_header_ids() {
+        let ids = Arc::new(Mutex::new(MultiSet::new()));
+        let options = options().with_header_ids(ids);
+        parse_fragment("## Intro\n", &options);
+        assert_eq!(header_ids(&parse_markdown("## Intro\n", &options).content), ["Intro"]);
+        assert_ne!(header_ids(&parse_markdown("## Intro\n", &options).content), ["Intro"]);
+    }
+
+    // run with `cargo test --release -- --ignored --nocapture` to see the timing
+    #[test]
+    #[ignore]
+    fn bench_one_megabyte_document() {
+        let section = "## Section\n\nSome *text* with `code`, $x^2$, [a link](https://a.example/x) and snake_case words.\n\n- item one\n- item **two**\n\n| a | b |\n|---|---|\n| 1 | 2 |\n\n> quoted _line_\n\n";
+        let doc = section.repeat((1 << 20) / section.len() + 1);
+        let start = std::time::Instant::now();
+        let parsed = parse_markdown(&doc, &options());
+        let elapsed = start.elapsed();
+        println!("parsed {} bytes into {} blocks in {:?}", doc.len(), parsed.content.len(), elapsed);
+        assert!(elapsed.as_secs() < 10);
+    }
+
+    #[test]
+    fn deep_nesting_does_not_overflow() {
+        let lists: String = (0..1000).map(|i| format!("{}- item\n", " ".repeat(i))).collect();
+        let doc = parse_markdown(&lists, &options());
+        assert!(doc.warnings.iter().any(|warning| warning.message.contains("nest")));
+
+        let emphasis = format!("{}x{}\n", "**_".repeat(1000), "_**".repeat(1000));
+        assert!(!parse_markdown(&emphasis, &options()).warnings.is_empty());
+
+        let notes = format!("{}x{}\n", "^[".repeat(1000), "]".repeat(1000));
+        parse_markdown(&notes, &options());
+        let containers = "<div>\n".repeat(1000) + &"</div>\n".repeat(1000);
+        parse_markdown(&containers, &options());
+        parse_markdown(&"> ".repeat(10000), &options());
+        parse_markdown(&"[".repeat(100000), &options());
+    }
+
+    #[test]
+    fn unmatched_delimiters_stay_linear() {
+        let start = std::time::Instant::now();
+        parse_markdown(&"*".repeat(100000), &options());
+        for delim in ["**a ", "_a ", "$", "$a ", "`", "``a ", "++", "[", "<a", "\\(", "|"] {
+            parse_markdown(&delim.repeat(100000 / delim.len()), &options());
+        }
+        parse_markdown(&"$a ".repeat(30000), &options().with_strict_math_delimiters(true));
+
+        // probes on one long line find the end of the line once
+        for span in ["**a** ", "`a` ", "$a$ ", "[a](b) ", "<http://a> ", "++a++ "] {
+            parse_markdown(&span.repeat(20000), &options());
+        }
+        assert!(start.elapsed().as_secs() < 5);
+    }
+
+    #[test]
+    fn id_prefixes_keep_documents_on_one_page_apart() {
+        let ids = Arc::new(Mutex::new(MultiSet::new()));
+        let first = parse_markdown("## Intro\n\n## Usage\n", &options().with_header_ids(Arc::clone(&ids)).with_id_prefix("doc1-"));
+        let second = parse_markdown("## Intro\n\n## Usage\n", &options().with_header_ids(ids).with_id_prefix("doc2-"));
+        assert_eq!(header_ids(&first.content), ["doc1-Intro", "doc1-Usage"]);
+        assert_eq!(header_ids(&second.content), ["doc2-Intro", "doc2-Usage"]);
+        assert!(gen_toc_html(&second.toc, &options()).contains("href=\"#doc2-Intro\""));
+    }
+
+    #[test]
+    fn conditions_keep_or_drop_nested_blocks() {
+        let doc = ":::if(internal)\ninternal\n:::if(beta)\nbeta\n:::\n:::\nafter\n";
+        let internal = html(doc, &options().with_conditions(["internal"]));
+        assert!(internal.contains("<p>internal</p>") && !internal.contains("beta") && internal.contains("<p>after</p>"));
+        let both = html(doc, &options().with_conditions(["internal", "beta"]));
+        assert!(both.contains("<p>beta</p>"));
+        let beta = html(doc, &options().with_conditions(["beta"]));
+        assert!(!beta.contains("internal") && !beta.contains("beta") && beta.contains("<p>after</p>"));
+    }
+
+    #[test]
+    fn unclosed_conditions_stay_linear() {
+        let start = std::time::Instant::now();
+        parse_markdown(&":::if(a)\n".repeat(20000), &options().with_conditions(["a"]));
+        parse_markdown(&":::if(a)\ntext\n".repeat(20000), &options());
+        assert!(start.elapsed().as_secs() < 5);
+    }
+
+    #[test]
+    fn link_cards_without_a_page_title_use_the_caption() {
+        let page = String::from("https://a.example/page");
+        let titled = OgpInfo { title: String::from("Fetched"), ..OgpInfo::default() };
+        let empty = options().with_metadata(HashMap::from([(page.clone(), OgpInfo::default())]));
+        let full = options().with_metadata(HashMap::from([(page, titled)]));
+        for (options, expected) in [(empty, "My Title"), (full, "Fetched")] {
+            match &parse_markdown("@[My Title](https://a.example/page)\n", &options).content[..] {
+                [LinkCard { title, .. }] => assert_eq!(title, expected),
+                content => panic!("expected a link card, got {:?}", content),
+            }
+        }
+    }
+
+    fn code_spans(doc: &str, options: &RenderOptions) -> Vec<String> {
+        let doc = parse_markdown(doc, options);
+        let Some(Paragraph { spans }) = doc.content.first() else { return Vec::new() };
+        spans.iter().filter_map(|span| match span {
+            PrimElem(Code { code }) => Some(code.clone()),
+            _ => None,
+        }).collect()
+    }
+
+    #[test]
+    fn code_spans_lose_one_surrounding_space() {
+        assert_eq!(code_spans("` x ` `  x  ` `  ` `x `\n", &options()), ["x", " x ", "  ", "x "]);
+        assert_eq!(code_spans("`` `a` `` ``` `` ```\n", &options()), ["`a`", "``"]);
+        assert_eq!(code_spans("` x ` `` `a` ``\n", &options().with_normalize_code_spans(false)), [" x ", " `a` "]);
+    }
+
+    #[test]
+    fn backtick_runs_close_only_on_a_run_of_the_same_length() {
+        assert_eq!(code_spans("``a`b`` and ```x `` y```\n", &options()), ["a`b", "x `` y"]);
+        let html = html("a `` b ` c\n\nd ` e\nf `g`\n", &options());
+        assert!(html.contains("<p>a `` b ` c</p>"));
+        assert!(html.contains("<p>d ` e</p>") && html.contains("f <code>g</code>"));
+    }
+
+    #[test]
+    fn shared_header_ids_are_not_locked_while_fetching() {
+        use std::io::{BufRead, BufReader, Write};
+        let ids = Arc::new(Mutex::new(MultiSet::new()));
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/page", listener.local_addr().unwrap());
+        let server = {
+            let ids = Arc::clone(&ids);
+            std::thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                // the request ends at the first empty line
+                let mut request = BufReader::new(&stream);
+                let mut line = String::new();
+                while request.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                let unlocked = ids.try_lock().is_ok();
+                let body = "<title>Fetched</title>";
+                write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body).unwrap();
+                unlocked
+            })
+        };
+        let options = options().with_offline(false).with_header_ids(Arc::clone(&ids));
+        let doc = parse_markdown(&format!("## Intro\n\n@[card]({})\n", url), &options);
+        assert!(server.join().unwrap());
+        assert!(matches!(&doc.content[1], LinkCard { title, .. } if title == "Fetched"));
+        assert_eq!(header_ids(&doc.content), ["Intro"]);
+        assert_eq!(ids.lock().unwrap().insert(String::from("Intro")), 1);
+    }
+
+    #[test]
+    fn a_poisoned_header_id_lock_is_still_used() {
+        let ids = Arc::new(Mutex::new(MultiSet::new()));
+        let options = options().with_header_ids(Arc::clone(&ids));
+        parse_markdown("## Intro\n", &options);
+        let poisoner = Arc::clone(&ids);
+        std::thread::spawn(move || {
+            let _guard = poisoner.lock().unwrap();
+            panic!("poison the lock");
+        }).join().unwrap_err();
+        assert!(ids.is_poisoned());
+        assert_ne!(header_ids(&parse_markdown("## Intro\n", &options).content), ["Intro"]);
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_parse_matches_sequential() {
+        let filler = "Some *text* with `code` and a [link](https://a.example/x).\n\n".repeat(400);
+        let mut source = String::from("---\ntitle: Doc\n---\n# Title\n\nintro^[first note]\n\n");
+        for i in 0..4 {
+            source.push_str(&format!("## Usage\n\ntext^[note {}]\n\n#### Deep\n\n### Detail\n\n{}", i, filler));
+        }
+        assert!(split_sections(&source).len() > 1);
+        let sequential = parse_markdown(&source, &options());
+        let parallel = parse_markdown(&source, &options().with_parallel(true));
+        assert_eq!(parallel.footnotes.len(), 5);
+        assert!(!parallel.warnings.is_empty());
+        assert_eq!(header_ids(&parallel.content), header_ids(&sequential.content));
+        assert_eq!(format!("{:?}", parallel.toc), format!("{:?}", sequential.toc));
+        assert_eq!(format!("{:?}", parallel.footnotes), format!("{:?}", sequential.footnotes));
+        assert_eq!(format!("{:?}", parallel), format!("{:?}", sequential));
+    }
+
+    // reparse the edit and check it against a full parse of the edited source
+    fn check_reparse(source: &str, edit: Range<usize>, text: &str) -> Range<usize> {
+        let mut doc = parse_markdown(source, &options());
+        let (new_source, changed) = reparse(&mut doc, source, edit, text, &options());
+        let full = parse_markdown(&new_source, &options());
+        assert_eq!(format!("{:?}", doc.content), format!("{:?}", full.content));
+        assert_eq!(doc.ranges, full.ranges);
+        assert_eq!(format!("{:?}", doc.warnings), format!("{:?}", full.warnings));
+        changed
+    }
+
+    #[test]
+    fn reparse_splits_a_paragraph() {
+        let source = "lead *open\n\nfirst second\n\nlast *open\n";
+        let split = source.find(" second").unwrap();
+        assert_eq!(check_reparse(source, split..split + 1, "\n\n*x "), 1..3);
+    }
+
+    #[test]
+    fn reparse_joins_two_paragraphs() {
+        let source = "lead\n\none *a\n\ntwo\n\nlast *b\n";
+        let gap = source.find("\n\ntwo").unwrap();
+        assert_eq!(check_reparse(source, gap..gap + 2, " "), 1..2);
+    }
+
+    #[test]
+    fn reparse_edits_the_end_of_the_document() {
+        let source = "lead *a\n\n| a | b |\n|---|---|\n| 1 |\n\nlast\n";
+        assert_eq!(check_reparse(source, source.len() - 1..source.len(), " *more\n\nnew\n"), 2..4);
+        assert_eq!(check_reparse(source, source.len()..source.len(), "\nappended *c\n"), 2..4);
+    }
+
+    #[test]
+    fn annotations_skip_the_spacing_before_the_note() {
+        let html = html("**a** {>>n<<} and word {>>m<<}\n", &options());
+        assert!(html.contains("<span class=\"annotation\" title=\"n\"><strong>a</strong></span>  and "));
+        assert!(html.contains("<span class=\"annotation\" title=\"m\">word </span>"));
+    }
+}
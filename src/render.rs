@@ -0,0 +1,76 @@
+use std::io;
+
+use crate::codegen::{gen_blocks_html, gen_html, gen_toc_html};
+use crate::data::{Block, Elem};
+use crate::options::RenderOptions;
+use crate::parser::parse_markdown;
+
+pub fn render_html(doc: &str, template: &[Elem], options: &RenderOptions) -> Result<String, io::Error> {
+    let document = parse_markdown(doc, options);
+    let mut html = Vec::new();
+    gen_html(&mut html, &document, template, options)?;
+    String::from_utf8(html).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+pub fn render_toc(doc: &str, options: &RenderOptions) -> String {
+    let document = parse_markdown(doc, options);
+    gen_toc_html(&document.toc, options)
+}
+
+// the header with the given id and the blocks under it, up to the next header of the same or higher level
+pub fn render_section(doc: &str, heading_id: &str, options: &RenderOptions) -> Option<String> {
+    let document = parse_markdown(doc, options);
+    let start = document.content.iter().position(|block| matches!(block, Block::Header { id, .. } if id == heading_id))?;
+    let Block::Header { level, .. } = &document.content[start] else {
+        return None;
+    };
+    let end = document.content[start + 1..].iter()
+        .position(|block| matches!(block, Block::Header { level: next, .. } if next <= level))
+        .map_or(document.content.len(), |i| start + 1 + i);
+    Some(gen_blocks_html(&document.content[start..end], 0, options).concat())
+}
+
+enum Change<'a> {
+    Same(&'a str),
+    Added(&'a str),
+    Removed(&'a str),
+}
+
+// blocks are compared by their rendered html and aligned by a longest common subsequence
+pub fn render_diff(old: &str, new: &str, options: &RenderOptions) -> String {
+    let old = gen_blocks_html(&parse_markdown(old, options).content, 2, options);
+    let new = gen_blocks_html(&parse_markdown(new, options).content, 2, options);
+
+    let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            changes.push(Change::Same(&old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            changes.push(Change::Removed(&old[i]));
+            i += 1;
+        } else {
+            changes.push(Change::Added(&new[j]));
+            j += 1;
+        }
+    }
+
+    let mut html = String::new();
+    for change in changes {
+        match change {
+            Change::Same(block) => html.push_str(block),
+            Change::Added(block) => html.push_str(&format!("<div class=\"added\">\n{}</div>\n", block)),
+            Change::Removed(block) => html.push_str(&format!("<div class=\"removed\">\n{}</div>\n", block)),
+        }
+    }
+    html
+}
@@ -0,0 +1,15 @@
+pub fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for word in text.split_whitespace() {
+        if !slug.is_empty() {
+            slug.push('-');
+        }
+        for c in word.chars() {
+            if c.is_ascii_punctuation() && c != '-' && c != '_' {
+                continue;
+            }
+            slug.extend(c.to_lowercase());
+        }
+    }
+    slug
+}
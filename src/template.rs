@@ -1,43 +1,103 @@
 use std::io::{self, BufRead, BufReader};
 use std::fs::File;
+use std::vec::IntoIter;
 use regex::Regex;
 
 use crate::data::Elem;
 use Elem::*;
 
+enum Chunk {
+    Elem(Elem),
+    If(String),
+    Else,
+    End,
+}
+
 pub fn read_template(path: &str) -> Result<Vec<Elem>, io::Error> {
     let file = File::open(path)?;
     let mut reader = BufReader::new(file);
     let mut line = String::new();
-    let mut template: Vec<Elem> = Vec::new();
-    let pattern = Regex::new("\\{[a-z]+\\}").unwrap();
+    let mut chunks: Vec<Chunk> = Vec::new();
+    let pattern = Regex::new("\\{\\{\\s*[A-Za-z0-9_.-]+\\s*\\}\\}|\\{(if|asset):[A-Za-z0-9_./-]+\\}|\\{[a-z]+\\}").unwrap();
 
     while reader.read_line(&mut line)? > 0 {
-        let mut text_iter = pattern.split(&line);
+        let text_iter = pattern.split(&line);
         let mut attr_iter = pattern.find_iter(&line);
-        loop {
-            if let Some(text) = text_iter.next() {
-                template.push(Str(text.to_string()));
-            } else {
-                break;
-            }
+        for text in text_iter {
+            chunks.push(Chunk::Elem(Str(text.to_string())));
             if let Some(attr) = attr_iter.next() {
-                template.push(match attr.as_str() {
-                    "{title}" => Title,
-                    "{year}" => Year,
-                    "{month}" => Month,
-                    "{day}" => Day,
-                    "{hour}" => Hour,
-                    "{minute}" => Minute,
-                    "{second}" => Second,
-                    "{toc}" => Toc(attr.start()),
-                    "{content}" => Content(attr.start()),
-                    _ => { println!("unknown attribute"); panic!(); },
+                chunks.push(match attr.as_str() {
+                    "{title}" => Chunk::Elem(Title),
+                    "{year}" => Chunk::Elem(Year),
+                    "{month}" => Chunk::Elem(Month),
+                    "{day}" => Chunk::Elem(Day),
+                    "{hour}" => Chunk::Elem(Hour),
+                    "{minute}" => Chunk::Elem(Minute),
+                    "{second}" => Chunk::Elem(Second),
+                    "{isodate}" => Chunk::Elem(IsoDate),
+                    "{jsonld}" => Chunk::Elem(JsonLd),
+                    "{tags}" => Chunk::Elem(Tags),
+                    "{toc}" => Chunk::Elem(Toc(attr.start())),
+                    "{content}" => Chunk::Elem(Content(attr.start())),
+                    "{else}" => Chunk::Else,
+                    "{end}" => Chunk::End,
+                    name if name.starts_with("{{") => Chunk::Elem(Var(name[2..name.len() - 2].trim().to_string())),
+                    name if name.starts_with("{asset:") => Chunk::Elem(Asset(name[7..name.len() - 1].to_string())),
+                    name if name.starts_with("{if:") => Chunk::If(name[4..name.len() - 1].to_string()),
+                    // braces that are not a known attribute, as in inline css, are kept as they are
+                    text => Chunk::Elem(Str(text.to_string())),
                 });
             }
         }
         line.clear();
     }
 
-    Ok(template)
-}
\ No newline at end of file
+    let mut chunks = chunks.into_iter();
+    match nest(&mut chunks) {
+        (template, None) => Ok(template),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "unbalanced {else} or {end} in template")),
+    }
+}
+
+// collect elements until an `{else}` or `{end}` that closes the current level
+fn nest(chunks: &mut IntoIter<Chunk>) -> (Vec<Elem>, Option<Chunk>) {
+    let mut elems = Vec::new();
+    while let Some(chunk) = chunks.next() {
+        match chunk {
+            Chunk::Elem(elem) => elems.push(elem),
+            Chunk::If(field) => {
+                let (body, term) = nest(chunks);
+                let else_body = match term {
+                    Some(Chunk::Else) => match nest(chunks) {
+                        (else_body, Some(Chunk::End)) => else_body,
+                        (_, term) => return (elems, term.or(Some(Chunk::Else))),
+                    },
+                    Some(Chunk::End) => Vec::new(),
+                    _ => return (elems, Some(Chunk::If(field))),
+                };
+                elems.push(IfPresent { field, body, else_body });
+            },
+            term => return (elems, Some(term)),
+        }
+    }
+    (elems, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_braces_are_literal() {
+        let path = std::env::temp_dir().join(format!("thinknaut-template-{}.html", std::process::id()));
+        std::fs::write(&path, "<style>p{color:red}</style>{foo}{if:tags}{tags}{end}\n").unwrap();
+        let template = read_template(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let text: String = template.iter().filter_map(|elem| match elem {
+            Str(text) => Some(text.as_str()),
+            _ => None,
+        }).collect();
+        assert_eq!(text, "<style>p{color:red}</style>{foo}\n");
+        assert!(template.iter().any(|elem| matches!(elem, IfPresent { field, .. } if field == "tags")));
+    }
+}